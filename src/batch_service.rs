@@ -0,0 +1,271 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:56
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Run a list of steps against a device without a terminal
+// The device output is copied to stdout as a transcript and the exit code tells if all steps succeeded
+// Without a terminal on stdin or stdout the same service passes stdin to the device (pipe mode)
+
+use crate::CmdLineConfig;
+use crate::config::FileConfig;
+use crate::console_service::build_script_envir;
+use crate::script_runner::{ScriptCommand, execute_script};
+use crate::term_switch::{TermSwitch, MsgType};
+
+use log::{error, info, trace};
+use std::io::{Read, Write};
+use std::sync::{Arc, atomic::AtomicU32, atomic::Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+// Time to wait for more device output after the last step
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+// Time allowed for a finished script to report its result
+const RESULT_TIME: Duration = Duration::from_secs(1);
+const POLL_TIME: Duration = Duration::from_millis(50);
+// Time without device output before pipe mode stops after stdin has closed
+const PIPE_IDLE: Duration = Duration::from_secs(1);
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchStep {
+    Send(Vec<u8>),
+    Expect(Vec<u8>),
+    Run(String),
+}
+
+
+// Collects the device output and finds the expected text after the previous match
+struct Transcript {
+    rx: Receiver<u8>,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Transcript {
+    fn find(&mut self, text: &[u8]) -> bool {
+        if text.is_empty() {
+            return true;
+        }
+        if let Some(idx) = self.data[self.pos..].windows(text.len()).position(|window| window == text) {
+            self.pos += idx + text.len();
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, text: &[u8], timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.find(text) {
+            match self.rx.recv_deadline(deadline) {
+                Ok(ch) => self.data.push(ch),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    fn settle(&mut self) {
+        while let Ok(ch) = self.rx.recv_timeout(SETTLE_TIME) {
+            self.data.push(ch);
+        }
+    }
+}
+
+
+// Copy the device output to stdout and to the transcript
+fn batch_output(console_rx: Receiver<MsgType>, transcript_tx: Option<Sender<u8>>) {
+    let mut stdout = std::io::stdout();
+    loop {
+        match console_rx.recv() {
+            Ok(MsgType::Console(ch)) => {
+                let _ = stdout.write_all(&[ch]);
+                if console_rx.is_empty() {
+                    let _ = stdout.flush();
+                }
+                if let Some(tx) = &transcript_tx {
+                    let _ = tx.send(ch);
+                }
+            }
+            Ok(MsgType::Exit) | Err(_) => break,
+            Ok(_) => (),
+        }
+    }
+    let _ = stdout.flush();
+}
+
+
+fn run_script(termswx: &TermSwitch, opts: &CmdLineConfig, fileconfig: &FileConfig, arg: &str) -> Result<(), String> {
+    let results = termswx.get_script_results();
+    let scripts = termswx.get_scripts();
+    // Only the batch runs scripts so the next result belongs to this script
+    results.lock().unwrap().clear();
+    let cmd = ScriptCommand {
+        tx: termswx.get_switch_tx(),
+        scripts: scripts.clone(),
+        envir: build_script_envir(opts, fileconfig, &results),
+        results: results.clone(),
+        python: fileconfig.get_python(),
+        // Nobody answers a script prompt in batch mode
+        in_prompt: Arc::new(AtomicU32::new(0)),
+        timeouts: fileconfig.get_script_timeouts(arg),
+        arg: String::from(arg),
+    };
+    execute_script(cmd).map_err(|e| format!("Script {} could not be started: {}", arg, e))?;
+    let deadline = Instant::now() + Duration::from_secs(opts.timeout);
+    let mut stopped: Option<Instant> = None;
+    loop {
+        if let Some(result) = results.lock().unwrap().back() {
+            if result.success {
+                return Ok(());
+            }
+            let mut text = format!("Script {} failed: {}", arg, result.status);
+            for line in result.stderr.iter() {
+                text.push_str(&format!("\n{}", line));
+            }
+            return Err(text);
+        }
+        // The script leaves the table just before its result is stored
+        if scripts.lock().unwrap().is_empty() {
+            let since = *stopped.get_or_insert_with(Instant::now);
+            if since.elapsed() > RESULT_TIME {
+                return Err(format!("Script {} could not be started", arg));
+            }
+        }
+        if Instant::now() > deadline {
+            for script in scripts.lock().unwrap().iter() {
+                script.stop();
+            }
+            return Err(format!("Timeout after {}s waiting for script {}", opts.timeout, arg));
+        }
+        thread::sleep(POLL_TIME);
+    }
+}
+
+
+fn run_steps(termswx: &TermSwitch, opts: &CmdLineConfig, fileconfig: &FileConfig, transcript: &mut Transcript)
+    -> Result<(), String> {
+    let switch_tx = termswx.get_switch_tx();
+    let timeout = Duration::from_secs(opts.timeout);
+    for step in opts.steps.iter() {
+        trace!("Batch step: {:?}", step);
+        match step {
+            BatchStep::Send(text) => {
+                for ch in text {
+                    switch_tx.send(MsgType::Console(*ch)).unwrap();
+                }
+            }
+            BatchStep::Expect(text) => {
+                if !transcript.expect(text, timeout) {
+                    return Err(format!("Timeout after {}s waiting for \"{}\"", opts.timeout,
+                                       String::from_utf8_lossy(text).escape_debug()));
+                }
+            }
+            BatchStep::Run(arg) => run_script(termswx, opts, fileconfig, arg)?,
+        }
+    }
+    Ok(())
+}
+
+
+// Run the steps and send quit when done: the thread returns the exit code
+pub fn start_batch(termswx: &TermSwitch, opts: &CmdLineConfig, fileconfig: FileConfig, quit_tx: Sender<()>)
+    -> thread::JoinHandle<i32> {
+    // The status messages are part of the transcript
+    crossterm::style::force_color_output(false);
+    let (transcript_tx, transcript_rx) = unbounded();
+    let console_rx = termswx.get_console_rx();
+    thread::spawn(move || batch_output(console_rx, Some(transcript_tx)));
+
+    let termswx = termswx.clone();
+    let opts = opts.clone();
+    thread::spawn(move || {
+        let mut transcript = Transcript { rx: transcript_rx, data: Vec::new(), pos: 0 };
+        let result = run_steps(&termswx, &opts, &fileconfig, &mut transcript);
+        transcript.settle();
+        let code = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                let _ = std::io::stdout().flush();
+                eprintln!("\n{}", e);
+                1
+            }
+        };
+        info!("Batch completed with exit code {}", code);
+        let _ = quit_tx.send(());
+        code
+    })
+}
+
+
+// Send stdin to the device and the device output to stdout
+// When stdin closes the program stops as soon as the device output has settled, except for a quiet server
+pub fn start_pipe(termswx: &TermSwitch, server: bool, quit_tx: Sender<()>) -> thread::JoinHandle<i32> {
+    crossterm::style::force_color_output(false);
+    if server {
+        termswx.hide();
+    }
+    let console_rx = termswx.get_console_rx();
+    thread::spawn(move || batch_output(console_rx, None));
+
+    let switch_tx = termswx.get_switch_tx();
+    let state = termswx.get_state();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        let mut stdin = std::io::stdin().lock();
+        loop {
+            match stdin.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(len) => {
+                    for ch in buffer[..len].iter() {
+                        switch_tx.send(MsgType::Console(*ch)).unwrap();
+                    }
+                }
+                Err(e) => {
+                    error!("Pipe input error: {}", e);
+                    break;
+                }
+            }
+        }
+        info!("Pipe input closed");
+        if server {
+            loop {
+                thread::park();
+            }
+        }
+        let mut received = state.received.load(Ordering::Relaxed);
+        let mut idle = Instant::now();
+        while idle.elapsed() < PIPE_IDLE {
+            thread::sleep(POLL_TIME);
+            let count = state.received.load(Ordering::Relaxed);
+            if count != received {
+                received = count;
+                idle = Instant::now();
+            }
+        }
+        let _ = quit_tx.send(());
+        0
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expect() {
+        let (tx, rx) = unbounded();
+        let mut transcript = Transcript { rx, data: Vec::new(), pos: 0 };
+        for ch in b"login: root\r\nPassword: \r\n# " {
+            tx.send(*ch).unwrap();
+        }
+        assert!(transcript.expect(b"login:", Duration::from_millis(10)));
+        assert!(transcript.expect(b"Password:", Duration::from_millis(10)));
+        // Text before the previous match is not found again
+        assert!(!transcript.expect(b"login:", Duration::from_millis(10)));
+        assert!(transcript.expect(b"# ", Duration::from_millis(10)));
+        assert!(!transcript.expect(b"# ", Duration::from_millis(10)));
+    }
+}
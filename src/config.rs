@@ -1,20 +1,31 @@
 //Steen Hegelund
-//Time-Stamp: 2025-Dec-28 16:49
+//Time-Stamp: 2026-Oct-18 19:55
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 //
 // Maintain configuration file and parse keyboard shortcuts
 
 use std::fs;
-use std::path::PathBuf;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::iter::zip;
 use std::num::Wrapping;
 use log::trace;
 use std::env;
 
 
+// Supported file transfer protocols
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferProtocol {
+    Xmodem,
+    Xmodem1k,
+    Ymodem,
+    Zmodem,
+    Kermit,
+}
+
+
 // Supported commands for keyboard shortcuts
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TermCommand {
     HelpMenu,
     Nop,
@@ -26,11 +37,126 @@ pub enum TermCommand {
     RunScript(String),
     SttySize,
     Environment,
+    TakeControl,
+    KickClients,
+    KickClient(String),
+    NextDevice,
+    PreviousDevice,
+    SelectDevice(usize),
+    SplitView,
+    FocusPane,
+    StatusBar,
+    LineMode,
     Prompt(String),
+    SendFile(TransferProtocol, String),
+    ReceiveFile(TransferProtocol, String),
+    Upload(String, String),
+    Download(String, String),
+}
+
+
+// Which of the running scripts a routing rule applies to (ordered by start time)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptRoute {
+    All,
+    First,
+    Last,
+}
+
+
+// Routing of device output to scripts and of script output to the device
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptRouting {
+    pub output: ScriptRoute,
+    pub write: ScriptRoute,
+}
+
+
+// Wall-clock and idle output timeouts for a script: None means no timeout
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScriptTimeouts {
+    pub timeout: Option<Duration>,
+    pub idle: Option<Duration>,
+}
+
+
+// Encoding used to transfer files to a target that only has a shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellEncoding {
+    Base64,
+    Printf,
+}
+
+
+// Command used on the target to verify a file transferred via the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellChecksum {
+    Sha256,
+    Md5,
+    Disabled,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShellTransfer {
+    pub encoding: ShellEncoding,
+    pub checksum: ShellChecksum,
+}
+
+
+// How the console handles the line endings of the keyboard input and the device output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEndings {
+    pub localecho: bool,
+    pub enter: Vec<u8>,
+    pub lftocrlf: bool,
+    pub stripcr: bool,
+}
+
+
+// Observers only see the device output, writers can also send input to the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientRole {
+    Observer,
+    #[default]
+    Writer,
+}
+
+
+// Access control for remote TCP clients: an empty password disables the handshake
+#[derive(Debug, Clone, Default)]
+pub struct ServerAccess {
+    pub password: String,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub role: ClientRole,
+    pub writers: Vec<String>,
+    pub observers: Vec<String>,
+}
+
+
+// Certificate files for TLS connections: the server needs a certificate and the client a CA
+#[derive(Debug, Clone, Default)]
+pub struct TlsFiles {
+    pub certificate: String,
+    pub key: String,
+    pub ca: String,
+    pub verifyclients: bool,
+}
+
+
+// A device defined in the configuration file: zero or empty values use the command line settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceProfile {
+    pub path: String,
+    pub baudrate: u32,
+    pub portnum: u16,
+    pub log: String,
 }
 
 
 // Sequence of keys and matching command
+#[derive(Clone)]
 pub struct ShortCut {
     pub keyname: String,
     pub keyseq: Vec<u8>,
@@ -41,6 +167,7 @@ pub struct ShortCut {
 pub type KeyConfig = Vec<ShortCut>;
 
 
+#[derive(Clone)]
 pub struct FileConfig {
     pub shortcuts: KeyConfig,
     pub config: toml::Table,
@@ -121,6 +248,10 @@ impl FileConfig {
         None
     }
 
+    pub fn find_keyseq(&self, cmd: TermCommand) -> Option<Vec<u8>> {
+        self.shortcuts.iter().find(|elem| elem.command == cmd).map(|elem| elem.keyseq.clone())
+    }
+
     pub fn print_shortcuts(&self) {
         for elem in self.shortcuts.iter() {
             println!("  {} => {:?}", elem.keyname, to_command(elem));
@@ -142,6 +273,250 @@ impl FileConfig {
         }
         String::from("/usr/bin/python3")
     }
+
+    pub fn get_maxscripts(&self) -> usize {
+        if let Some(toml::Value::Table(scripting)) = self.config.get("scripting") {
+            if let Some(toml::Value::Integer(maxscripts)) = scripting.get("maxscripts") {
+                return usize::try_from(*maxscripts).unwrap_or(1).max(1);
+            }
+        }
+        4
+    }
+
+    // Size in bytes of the device output replayed to new remote clients
+    pub fn get_history_size(&self) -> usize {
+        if let Some(toml::Value::Table(server)) = self.config.get("server") {
+            if let Some(toml::Value::Integer(historykb)) = server.get("historykb") {
+                return usize::try_from(*historykb).unwrap_or(0) * 1024;
+            }
+        }
+        16 * 1024
+    }
+
+    // Send a stty command to the device when the terminal has been resized
+    pub fn get_resize_stty(&self) -> bool {
+        if let Some(toml::Value::Table(resize)) = self.config.get("resize") {
+            if let Some(toml::Value::Boolean(stty)) = resize.get("stty") {
+                return *stty;
+            }
+        }
+        false
+    }
+
+    // The end of the device output when a shell waits for a command
+    pub fn get_prompts(&self) -> Vec<String> {
+        if let Some(toml::Value::Table(resize)) = self.config.get("resize") {
+            if resize.contains_key("prompts") {
+                return to_strings(resize.get("prompts"));
+            }
+        }
+        vec![String::from("$ "), String::from("# ")]
+    }
+
+    // Folder with the xterm.js files for the web terminal: empty to load them from the CDN
+    pub fn get_web_assets(&self) -> String {
+        if let Some(toml::Value::Table(web)) = self.config.get("web") {
+            if let Some(toml::Value::String(assets)) = web.get("assets") {
+                if !assets.is_empty() {
+                    return subst_home(assets);
+                }
+            }
+        }
+        String::new()
+    }
+
+    // Words that can be completed with Tab in the line editing mode
+    pub fn get_line_words(&self) -> Vec<String> {
+        if let Some(toml::Value::Table(lineedit)) = self.config.get("lineedit") {
+            return to_strings(lineedit.get("words"));
+        }
+        Vec::new()
+    }
+
+    pub fn get_line_endings(&self) -> LineEndings {
+        let mut settings = LineEndings {
+            localecho: false,
+            enter: vec![b'\r'],
+            lftocrlf: false,
+            stripcr: false,
+        };
+        if let Some(toml::Value::Table(console)) = self.config.get("console") {
+            if let Some(toml::Value::Boolean(localecho)) = console.get("localecho") {
+                settings.localecho = *localecho;
+            }
+            if let Some(toml::Value::String(enter)) = console.get("enter") {
+                settings.enter = match enter.as_str() {
+                    "lf" => b"\n".to_vec(),
+                    "crlf" => b"\r\n".to_vec(),
+                    _ => b"\r".to_vec(),
+                };
+            }
+            if let Some(toml::Value::Boolean(lftocrlf)) = console.get("lftocrlf") {
+                settings.lftocrlf = *lftocrlf;
+            }
+            if let Some(toml::Value::Boolean(stripcr)) = console.get("stripcr") {
+                settings.stripcr = *stripcr;
+            }
+        }
+        settings
+    }
+
+    pub fn get_script_routing(&self) -> ScriptRouting {
+        let mut routing = ScriptRouting {
+            output: ScriptRoute::All,
+            write: ScriptRoute::All,
+        };
+        if let Some(toml::Value::Table(scripting)) = self.config.get("scripting") {
+            if let Some(toml::Value::String(route)) = scripting.get("output") {
+                routing.output = to_script_route(route);
+            }
+            if let Some(toml::Value::String(route)) = scripting.get("write") {
+                routing.write = to_script_route(route);
+            }
+        }
+        routing
+    }
+
+    // Encoding and checksum command used to transfer files via the target shell
+    pub fn get_shell_transfer(&self) -> ShellTransfer {
+        let mut settings = ShellTransfer {
+            encoding: ShellEncoding::Base64,
+            checksum: ShellChecksum::Sha256,
+        };
+        if let Some(toml::Value::Table(shelltransfer)) = self.config.get("shelltransfer") {
+            if let Some(toml::Value::String(encoding)) = shelltransfer.get("encoding") {
+                if encoding == "printf" {
+                    settings.encoding = ShellEncoding::Printf;
+                }
+            }
+            if let Some(toml::Value::String(checksum)) = shelltransfer.get("checksum") {
+                settings.checksum = match checksum.as_str() {
+                    "md5sum" => ShellChecksum::Md5,
+                    "none" => ShellChecksum::Disabled,
+                    _ => ShellChecksum::Sha256,
+                };
+            }
+        }
+        settings
+    }
+
+    pub fn get_server_access(&self) -> ServerAccess {
+        let mut access = ServerAccess::default();
+        if let Some(toml::Value::Table(server)) = self.config.get("server") {
+            if let Some(toml::Value::String(password)) = server.get("password") {
+                access.password = password.clone();
+            }
+            access.allow = to_strings(server.get("allow"));
+            access.deny = to_strings(server.get("deny"));
+            if let Some(toml::Value::String(role)) = server.get("role") {
+                if role == "observer" {
+                    access.role = ClientRole::Observer;
+                }
+            }
+            access.writers = to_strings(server.get("writers"));
+            access.observers = to_strings(server.get("observers"));
+        }
+        access
+    }
+
+    pub fn get_tls_files(&self) -> TlsFiles {
+        let mut files = TlsFiles::default();
+        if let Some(toml::Value::Table(tls)) = self.config.get("tls") {
+            let path = |name: &str| match tls.get(name) {
+                Some(toml::Value::String(path)) if !path.is_empty() => subst_home(path),
+                _ => String::new(),
+            };
+            files.certificate = path("certificate");
+            files.key = path("key");
+            files.ca = path("ca");
+            if let Some(toml::Value::Boolean(verify)) = tls.get("verifyclients") {
+                files.verifyclients = *verify;
+            }
+        }
+        files
+    }
+
+    pub fn get_device_profile(&self, name: &str) -> Option<DeviceProfile> {
+        if let Some(toml::Value::Table(devices)) = self.config.get("devices") {
+            if let Some(toml::Value::Table(profile)) = devices.get(name) {
+                return to_device_profile(profile);
+            }
+        }
+        None
+    }
+
+    // Get the global script timeouts and override them with the timeouts for the script file if found
+    pub fn get_script_timeouts(&self, arg: &str) -> ScriptTimeouts {
+        let mut timeouts = ScriptTimeouts::default();
+        if let Some(toml::Value::Table(scripting)) = self.config.get("scripting") {
+            update_timeouts(&mut timeouts, scripting);
+        }
+        let script = arg.split(' ').next().unwrap_or_default();
+        let filename = Path::new(script).file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if let Some(toml::Value::Table(scripttimeouts)) = self.config.get("scripttimeouts") {
+            for (name, value) in scripttimeouts.iter() {
+                if name == script || name == filename {
+                    if let toml::Value::Table(table) = value {
+                        update_timeouts(&mut timeouts, table);
+                    }
+                }
+            }
+        }
+        timeouts
+    }
+}
+
+
+// Use the timeouts found in a configuration table: zero disables the timeout
+fn update_timeouts(timeouts: &mut ScriptTimeouts, table: &toml::Table) {
+    if let Some(toml::Value::Integer(secs)) = table.get("timeout") {
+        timeouts.timeout = u64::try_from(*secs).ok().filter(|secs| *secs > 0).map(Duration::from_secs);
+    }
+    if let Some(toml::Value::Integer(secs)) = table.get("idletimeout") {
+        timeouts.idle = u64::try_from(*secs).ok().filter(|secs| *secs > 0).map(Duration::from_secs);
+    }
+}
+
+
+// A profile needs a device path, the other settings are optional
+fn to_device_profile(table: &toml::Table) -> Option<DeviceProfile> {
+    let Some(toml::Value::String(path)) = table.get("path") else {
+        return None;
+    };
+    let mut profile = DeviceProfile {
+        path: subst_home(path),
+        ..Default::default()
+    };
+    if let Some(toml::Value::Integer(baudrate)) = table.get("baudrate") {
+        profile.baudrate = u32::try_from(*baudrate).unwrap_or(0);
+    }
+    if let Some(toml::Value::Integer(portnum)) = table.get("portnum") {
+        profile.portnum = u16::try_from(*portnum).unwrap_or(0);
+    }
+    if let Some(toml::Value::String(log)) = table.get("log") {
+        profile.log = subst_home(log);
+    }
+    Some(profile)
+}
+
+
+// Convert a list of strings from the configuration file
+fn to_strings(value: Option<&toml::Value>) -> Vec<String> {
+    match value {
+        Some(toml::Value::Array(items)) => items.iter()
+            .filter_map(|item| item.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+
+fn to_script_route(name: &str) -> ScriptRoute {
+    match name {
+        "first" => ScriptRoute::First,
+        "last" => ScriptRoute::Last,
+        _ => ScriptRoute::All,
+    }
 }
 
 // Write default config in toml file an return it for immediate use
@@ -154,6 +529,40 @@ fn create_new_config(filename: &PathBuf, config_version: i64) -> toml::Table {
             "TERM" = "xterm"
         [scripting]
             "python" = "python3"
+            "maxscripts" = 4
+            "output" = "all"
+            "write" = "all"
+            "timeout" = 0
+            "idletimeout" = 0
+        [shelltransfer]
+            "encoding" = "base64"
+            "checksum" = "sha256sum"
+        [server]
+            "password" = ""
+            "allow" = []
+            "deny" = []
+            "role" = "writer"
+            "writers" = []
+            "observers" = []
+            "historykb" = 16
+        [tls]
+            "certificate" = ""
+            "key" = ""
+            "ca" = ""
+            "verifyclients" = false
+        [devices]
+        [console]
+            "localecho" = false
+            "enter" = "cr"
+            "lftocrlf" = false
+            "stripcr" = false
+        [lineedit]
+            "words" = []
+        [web]
+            "assets" = ""
+        [resize]
+            "stty" = false
+            "prompts" = ["$ ", "# "]
         [keynames]
             "F1" = "\x1bOP"
             "F2" = "\x1bOQ"
@@ -278,6 +687,62 @@ fn create_keymap(config: &toml::Table) -> KeyConfig {
                                     command: TermCommand::Quit,
                                 });
                             }
+                            "control" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::TakeControl,
+                                });
+                            }
+                            "kick" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::KickClients,
+                                });
+                            }
+                            "next" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::NextDevice,
+                                });
+                            }
+                            "previous" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::PreviousDevice,
+                                });
+                            }
+                            "split" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::SplitView,
+                                });
+                            }
+                            "focus" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::FocusPane,
+                                });
+                            }
+                            "statusbar" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::StatusBar,
+                                });
+                            }
+                            "linemode" => {
+                                keyconfig.push(ShortCut {
+                                    keyname: key.to_string(),
+                                    keyseq: keyseq.into(),
+                                    command: TermCommand::LineMode,
+                                });
+                            }
                             _ => (),
                         }
                         if let Some((cmd, arg)) = cmdstr.split_once(' ') {
@@ -315,6 +780,66 @@ fn create_keymap(config: &toml::Table) -> KeyConfig {
                                         command: TermCommand::RunScript(filename),
                                     });
                                 }
+                                "send" => {
+                                    if let Some((protocol, filename)) = to_transfer(arg) {
+                                        keyconfig.push(ShortCut {
+                                            keyname: key.to_string(),
+                                            keyseq: keyseq.into(),
+                                            command: TermCommand::SendFile(protocol, filename),
+                                        });
+                                    }
+                                }
+                                "receive" => {
+                                    if let Some((protocol, filename)) = to_transfer(arg) {
+                                        keyconfig.push(ShortCut {
+                                            keyname: key.to_string(),
+                                            keyseq: keyseq.into(),
+                                            command: TermCommand::ReceiveFile(protocol, filename),
+                                        });
+                                    }
+                                }
+                                "download" => {
+                                    // The local path defaults to the current folder
+                                    let (remote, local) = arg.split_once(' ').unwrap_or((arg, "."));
+                                    keyconfig.push(ShortCut {
+                                        keyname: key.to_string(),
+                                        keyseq: keyseq.into(),
+                                        command: TermCommand::Download(String::from(remote), String::from(local.trim())),
+                                    });
+                                }
+                                "upload" => {
+                                    // The target path defaults to the filename in the current folder
+                                    let (local, remote) = match arg.split_once(' ') {
+                                        Some((local, remote)) => (local, remote.trim()),
+                                        None => (arg, Path::new(arg).file_name().and_then(|name| name.to_str())
+                                                 .unwrap_or_default()),
+                                    };
+                                    keyconfig.push(ShortCut {
+                                        keyname: key.to_string(),
+                                        keyseq: keyseq.into(),
+                                        command: TermCommand::Upload(String::from(local), String::from(remote)),
+                                    });
+                                }
+                                "kick" => {
+                                    // An IP address or an IP address and port
+                                    keyconfig.push(ShortCut {
+                                        keyname: key.to_string(),
+                                        keyseq: keyseq.into(),
+                                        command: TermCommand::KickClient(String::from(arg.trim())),
+                                    });
+                                }
+                                "device" => {
+                                    // Devices are numbered from 1 in the order they were given
+                                    if let Ok(number) = arg.trim().parse::<usize>() {
+                                        if number > 0 {
+                                            keyconfig.push(ShortCut {
+                                                keyname: key.to_string(),
+                                                keyseq: keyseq.into(),
+                                                command: TermCommand::SelectDevice(number - 1),
+                                            });
+                                        }
+                                    }
+                                }
                                 _ => (),
                             }
                         }
@@ -327,6 +852,21 @@ fn create_keymap(config: &toml::Table) -> KeyConfig {
 }
 
 
+// Get the protocol and the file path from the arguments of a file transfer command
+fn to_transfer(arg: &str) -> Option<(TransferProtocol, String)> {
+    let (name, filename) = arg.split_once(' ')?;
+    let protocol = match name {
+        "xmodem" => TransferProtocol::Xmodem,
+        "xmodem1k" => TransferProtocol::Xmodem1k,
+        "ymodem" => TransferProtocol::Ymodem,
+        "zmodem" => TransferProtocol::Zmodem,
+        "kermit" => TransferProtocol::Kermit,
+        _ => return None,
+    };
+    Some((protocol, String::from(filename)))
+}
+
+
 // Return abs(x - y) when x and y are u8 values
 fn u8diff(elem: (&u8, &u8)) -> u8 {
     (Wrapping(*elem.0) - Wrapping(*elem.1)).0
@@ -377,3 +917,107 @@ pub fn subst_home(arg: &String) -> String {
 
     arg.replace("~", &home)
 }
+
+
+// Text sent to a device can contain the escapes \r \n \t \e \\ and \xNN
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            let mut buf = [0; 4];
+            result.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => result.push(b'\r'),
+            Some('n') => result.push(b'\n'),
+            Some('t') => result.push(b'\t'),
+            Some('e') => result.push(0x1b),
+            Some('\\') => result.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(value) if hex.len() == 2 => result.push(value),
+                    _ => return Err(format!("Invalid escape: \\x{}", hex)),
+                }
+            }
+            Some(other) => return Err(format!("Invalid escape: \\{}", other)),
+            None => return Err(String::from("Incomplete escape at the end of the text")),
+        }
+    }
+    Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_profile() {
+        let table = toml::toml! {
+            "path" = "/dev/ttyUSB3"
+            "baudrate" = 9600
+            "log" = "/tmp/board3.log"
+        };
+        assert_eq!(to_device_profile(&table), Some(DeviceProfile {
+            path: String::from("/dev/ttyUSB3"),
+            baudrate: 9600,
+            portnum: 0,
+            log: String::from("/tmp/board3.log"),
+        }));
+        let table = toml::toml! {
+            "portnum" = 7301
+        };
+        assert_eq!(to_device_profile(&table), None);
+    }
+
+    #[test]
+    fn device_keymap() {
+        let config = toml::toml! {
+            [keynames]
+                "F11" = "\x1b[23~"
+                "F12" = "\x1b[24~"
+                "Ctrl+a" = "\x01"
+            [keymap]
+                "F11" = "previous"
+                "F12" = "next"
+                "Ctrl+a" = "device 3"
+        };
+        let keymap = create_keymap(&config);
+        let commands: Vec<&TermCommand> = keymap.iter().map(|elem| &elem.command).collect();
+        assert!(commands.contains(&&TermCommand::PreviousDevice));
+        assert!(commands.contains(&&TermCommand::NextDevice));
+        assert!(commands.contains(&&TermCommand::SelectDevice(2)));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape("ls -l\\n"), Ok(b"ls -l\n".to_vec()));
+        assert_eq!(unescape("\\x03\\e[A\\t\\\\"), Ok(b"\x03\x1b[A\t\\".to_vec()));
+        assert_eq!(unescape("æ"), Ok("æ".as_bytes().to_vec()));
+        assert!(unescape("\\x3").is_err());
+        assert!(unescape("\\q").is_err());
+        assert!(unescape("end\\").is_err());
+    }
+
+    #[test]
+    fn line_endings() {
+        let config = toml::toml! {
+            [console]
+                "localecho" = true
+                "enter" = "crlf"
+                "stripcr" = true
+        };
+        let fileconfig = FileConfig { shortcuts: Vec::new(), config };
+        assert_eq!(fileconfig.get_line_endings(), LineEndings {
+            localecho: true,
+            enter: b"\r\n".to_vec(),
+            lftocrlf: false,
+            stripcr: true,
+        });
+        let fileconfig = FileConfig { shortcuts: Vec::new(), config: toml::Table::new() };
+        assert_eq!(fileconfig.get_line_endings().enter, b"\r");
+    }
+}
@@ -1,5 +1,5 @@
 //Steen Hegelund
-//Time-Stamp: 2025-Apr-07 14:47
+//Time-Stamp: 2026-Oct-18 19:58
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 //
 // Handle input from the local console and looking up keyboard shortcuts
@@ -7,28 +7,67 @@
 
 use crate::CmdLineConfig;
 use crate::term_switch::{TermSwitch, MsgType};
-use crate::config::{TermCommand, dump_keyseq, FileConfig, subst_home};
-use crate::script_runner::{signal, ScriptCommand, execute_script};
+use crate::config::{TermCommand, dump_keyseq, FileConfig, LineEndings, subst_home};
+use crate::script_runner::{ScriptCommand, ScriptResults, execute_script};
+use crate::file_transfer::{TransferCommand, start_transfer};
+use crate::shell_transfer::{UploadCommand, DownloadCommand, start_upload, start_download};
+use crate::split_view::{SplitView, SharedView, MAX_PANES, start_render};
+use crate::line_editor::LineEditor;
+use crate::status_bar::{StatusBar, StatusDevice, SharedStatus, start_refresh, output_rows};
 
 use log::{error, trace};
 use std::thread::sleep;
-use std::io::{self, Write, Read};
+use std::io::{self, IsTerminal, Write, Read};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::env;
 use crossterm::terminal;
 use crossterm::execute;
 use crossterm::style::{Color, Stylize};
-use std::sync::{Arc, atomic::AtomicBool, atomic::AtomicI8, atomic::Ordering};
+use std::sync::{Arc, Mutex, atomic::AtomicBool, atomic::AtomicU32, atomic::AtomicUsize, atomic::Ordering};
 use std::collections::HashMap;
+use std::iter::zip;
+use crossbeam_channel::{Receiver, Sender};
 use std::fs::canonicalize;
 
+// How often the terminal size is checked
+const RESIZE_INTERVAL: Duration = Duration::from_millis(500);
+// How often the resize signal flag is checked
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(20);
+// The remote clients in the help menu are selected with the keys a to z
+const MENU_CLIENTS: usize = 26;
+
+// Set by the SIGWINCH handler
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+
+// A device and its term switch as seen from the local console
+struct ConsoleDevice {
+    opts: CmdLineConfig,
+    termswx: TermSwitch,
+}
+
+
+// The port number or the addresses when the server only listens on some of them
+fn listen_text(cmdopts: &CmdLineConfig, portnum: u16, listen: &[std::net::SocketAddr]) -> String {
+    let tls = if cmdopts.tls { " with TLS" } else { "" };
+    if cmdopts.address.is_empty() {
+        return format!("port {}{}", portnum, tls);
+    }
+    let addrs: Vec<String> = listen.iter().map(|addr| addr.to_string()).collect();
+    format!("{}{}", addrs.join(", "), tls)
+}
+
 
 fn banner(cmdopts: &CmdLineConfig, helpkey: String) {
     let lines = vec![
         (true, 100, format!("=== Welcome to TermSWX").with(Color::White).on(Color::Black)),
         (cmdopts.portnum > 0, 100, format!(" => ").on(Color::White)),
-        (cmdopts.portnum > 0, 100, format!("Listening on port {}", cmdopts.portnum).with(Color::DarkCyan).on(Color::White)),
+        (cmdopts.portnum > 0, 100, format!("Listening on {}", listen_text(cmdopts, cmdopts.portnum, &cmdopts.listen))
+            .with(Color::DarkCyan).on(Color::White)),
+        (cmdopts.webport > 0, 100, " => ".to_string().on(Color::White)),
+        (cmdopts.webport > 0, 100, format!("Web terminal on {}", listen_text(cmdopts, cmdopts.webport, &cmdopts.weblisten))
+            .with(Color::DarkCyan).on(Color::White)),
         (cmdopts.networkdev, 100, format!(" => ").on(Color::White)),
         (cmdopts.networkdev, 100, format!("Connected to {:?}", cmdopts.device).with(Color::Red).on(Color::White)),
         (true, 100, format!(" => ").on(Color::White)),
@@ -67,13 +106,17 @@ fn wait_for_exit(fileconfig: FileConfig) -> Result<thread::JoinHandle<()>,u32> {
 
 
 pub fn show_error(msg: Vec<String>) {
-    terminal::disable_raw_mode().unwrap();
+    // Only the interactive console uses raw mode
+    let raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+    let _ = terminal::disable_raw_mode();
     println!("");
     for line in msg {
         println!("{}", line.with(Color::White).on(Color::DarkRed));
     }
     println!("");
-    terminal::enable_raw_mode().unwrap();
+    if raw {
+        terminal::enable_raw_mode().unwrap();
+    }
 }
 
 
@@ -88,8 +131,320 @@ fn show_duration_str(secs: u64) -> String {
 }
 
 
+fn device_text(opts: &CmdLineConfig) -> String {
+    if opts.portnum > 0 {
+        format!("{} ({}, port {})", opts.name, opts.device.display(), opts.portnum)
+    } else {
+        format!("{} ({})", opts.name, opts.device.display())
+    }
+}
+
+
+// Show the active device and the other devices that had output while they were hidden
+fn show_device_status(devices: &[ConsoleDevice], active: usize) {
+    let mut text = format!("=== Device {} of {}: {}", active + 1, devices.len(), device_text(&devices[active].opts));
+    let unseen: Vec<String> = devices.iter().enumerate()
+        .filter(|(idx, device)| *idx != active && device.termswx.has_unseen())
+        .map(|(idx, device)| format!("{} {}", idx + 1, device.opts.name))
+        .collect();
+    if !unseen.is_empty() {
+        text.push_str(&format!(" - new output: {}", unseen.join(", ")));
+    }
+    text.push_str(" ===");
+    println!("\r\n{}\r", text.with(Color::White).on(Color::DarkMagenta));
+}
+
+
+// The replayed output of a device is limited to a screenful
+// A pty without a window size reports zero rows
+fn screen_rows() -> usize {
+    match terminal::size() {
+        Ok((_, rows)) if rows > 0 => rows as usize,
+        _ => 24,
+    }
+}
+
+
+// Show another device on the console and return the new active device
+fn select_device(devices: &[ConsoleDevice], active: usize, next: usize) -> usize {
+    if next >= devices.len() {
+        println!("\r{}\r", format!("No device {}", next + 1).with(Color::Red));
+        return active;
+    }
+    show_device_status(devices, next);
+    if next != active {
+        trace!("Switch from device {} to {}", devices[active].opts.name, devices[next].opts.name);
+        devices[active].termswx.hide();
+        devices[next].termswx.show(screen_rows());
+    }
+    next
+}
+
+
+// In the split view the device keys move the focus between the panes
+fn step_device(devices: &[ConsoleDevice], active: usize, view: &SharedView, forward: bool) -> usize {
+    if let Some(split) = view.lock().unwrap().as_mut() {
+        return split.move_focus(forward);
+    }
+    let count = devices.len();
+    select_device(devices, active, if forward { (active + 1) % count } else { (active + count - 1) % count })
+}
+
+
+// Show the active device and the following devices in panes, or go back to showing the active device
+fn toggle_split(devices: &[ConsoleDevice], active: usize, view: &SharedView, status: &SharedStatus) {
+    let mut split = view.lock().unwrap();
+    if split.take().is_some() {
+        drop(split);
+        execute!(std::io::stdout(), crossterm::terminal::LeaveAlternateScreen).unwrap();
+        pause_status(status, false);
+        for device in devices.iter() {
+            device.termswx.hide();
+        }
+        show_device_status(devices, active);
+        devices[active].termswx.show(screen_rows());
+        return;
+    }
+    if devices.len() < 2 {
+        println!("\r{}\r", "The split view needs at least two devices".with(Color::Red));
+        return;
+    }
+    let panes: Vec<(usize, String)> = (0..devices.len().min(MAX_PANES))
+        .map(|pos| (active + pos) % devices.len())
+        .map(|idx| (idx, devices[idx].opts.name.clone()))
+        .collect();
+    for device in devices.iter() {
+        device.termswx.hide();
+    }
+    pause_status(status, true);
+    execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen,
+             crossterm::terminal::Clear(crossterm::terminal::ClearType::All)).unwrap();
+    *split = Some(SplitView::new(panes));
+    // The devices replay their recent output into the panes
+    for idx in split.as_ref().unwrap().devices() {
+        devices[idx].termswx.show(screen_rows());
+    }
+}
+
+
+fn pause_split(view: &SharedView, paused: bool) -> bool {
+    match view.lock().unwrap().as_mut() {
+        Some(split) => {
+            split.pause(paused);
+            true
+        }
+        None => false,
+    }
+}
+
+
+// Show the status bar of the active device or remove it
+fn toggle_status(devices: &[ConsoleDevice], active: &Arc<AtomicUsize>, status: &SharedStatus, split: bool) {
+    let mut status = status.lock().unwrap();
+    if let Some(mut bar) = status.take() {
+        let _ = bar.hide(&mut io::stdout());
+        return;
+    }
+    let list = devices.iter()
+        .map(|device| StatusDevice {
+            name: device.opts.name.clone(),
+            path: device.opts.device.display().to_string(),
+            networkdev: device.opts.networkdev,
+            termswx: device.termswx.clone(),
+        })
+        .collect();
+    // The split view uses the last row
+    let mut bar = StatusBar::new(list, active.clone(), split);
+    if !split {
+        let _ = bar.show(&mut io::stdout());
+    }
+    *status = Some(bar);
+}
+
+
+fn pause_status(status: &SharedStatus, paused: bool) {
+    if let Some(bar) = status.lock().unwrap().as_mut() {
+        let _ = bar.pause(paused, &mut io::stdout());
+    }
+}
+
+
+// The status bar is not drawn in the middle of the echo
+fn write_echo(status: &SharedStatus, echo: &[u8]) {
+    let _status = status.lock().unwrap();
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(echo).and_then(|_| stdout.flush());
+}
+
+
+// Show the keyboard input for devices that do not echo it: escape sequences and control keys are left out
+fn local_echo(input: &[u8]) -> Vec<u8> {
+    let mut echo = Vec::new();
+    if input.first() == Some(&0x1b) {
+        return echo;
+    }
+    for ch in input {
+        match *ch {
+            b'\r' => echo.extend_from_slice(b"\r\n"),
+            0x7f | 0x08 => echo.extend_from_slice(b"\x08 \x08"),
+            b'\t' => echo.push(b'\t'),
+            ch if ch < 0x20 => (),
+            ch => echo.push(ch),
+        }
+    }
+    echo
+}
+
+
+// The Enter key is sent as the configured line ending
+fn translate_enter(input: &[u8], endings: &LineEndings) -> Vec<u8> {
+    let mut output = Vec::new();
+    for ch in input {
+        if *ch == b'\r' {
+            output.extend_from_slice(&endings.enter);
+        } else {
+            output.push(*ch);
+        }
+    }
+    output
+}
+
+
+// Send the keyboard input to the device and echo it locally when configured
+// The line editor shows its own input
+fn send_input(switch_tx: &Sender<MsgType>, input: &[u8], endings: &LineEndings, status: &SharedStatus, echo: bool) {
+    if echo && endings.localecho {
+        write_echo(status, &local_echo(input));
+    }
+    for val in translate_enter(input, endings) {
+        if switch_tx.send(MsgType::Console(val)).is_err() {
+            error!("Cannot send console input to term_switch");
+        }
+    }
+}
+
+
+// A stripped CR is added again before the LF so the lines still start at the left margin
+fn translate_output(ch: u8, previous: u8, endings: &LineEndings) -> Vec<u8> {
+    match ch {
+        b'\r' if endings.stripcr => Vec::new(),
+        b'\n' if endings.lftocrlf && (previous != b'\r' || endings.stripcr) => b"\r\n".to_vec(),
+        _ => vec![ch],
+    }
+}
+
+
+// The device gets the size of the terminal without the status bar
+fn stty_command(status: &SharedStatus) -> String {
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    format!("stty cols {} rows {}\r", cols, output_rows(status, rows))
+}
+
+
+fn ends_with_prompt(output: &[u8], prompts: &[String]) -> bool {
+    prompts.iter().any(|prompt| !prompt.is_empty() && output.ends_with(prompt.as_bytes()))
+}
+
+
+// Follows the terminal size and reports a new size once it has been stable for one check
+struct ResizeTracker {
+    size: (u16, u16),
+    rows: u16,
+    pending: bool,
+}
+
+impl ResizeTracker {
+    fn new(size: (u16, u16), rows: u16) -> Self {
+        ResizeTracker { size, rows, pending: false }
+    }
+
+    // Returns true when the device should be told about the size
+    fn update(&mut self, size: (u16, u16), rows: u16) -> bool {
+        if size != self.size || rows != self.rows {
+            trace!("Terminal resized to {:?}", size);
+            self.size = size;
+            self.rows = rows;
+            self.pending = true;
+            return false;
+        }
+        self.pending
+    }
+
+    fn sent(&mut self) {
+        self.pending = false;
+    }
+}
+
+
+#[cfg(unix)]
+extern "C" fn on_resize(_signal: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+
+#[cfg(unix)]
+fn catch_resize() {
+    unsafe {
+        libc::signal(libc::SIGWINCH, on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+
+// Without a resize signal the size is only polled
+#[cfg(not(unix))]
+fn catch_resize() {
+}
+
+
+// Wait for a resize signal or for the next poll of the size
+fn wait_resize() {
+    let start = Instant::now();
+    while start.elapsed() < RESIZE_INTERVAL {
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        thread::sleep(SIGNAL_INTERVAL);
+    }
+}
+
+
+// Tell the active device about a new terminal size as soon as it shows a shell prompt
+fn watch_resize(switches: Vec<TermSwitch>, active: Arc<AtomicUsize>, status: SharedStatus, view: SharedView,
+                prompts: Vec<String>) {
+    catch_resize();
+    thread::spawn(move || {
+        let size = terminal::size().unwrap_or((80, 24));
+        let mut tracker = ResizeTracker::new(size, output_rows(&status, size.1));
+        loop {
+            wait_resize();
+            let size = terminal::size().unwrap_or(tracker.size);
+            // The panes have their own size
+            if !tracker.update(size, output_rows(&status, size.1)) || view.lock().unwrap().is_some() {
+                continue;
+            }
+            let termswx = &switches[active.load(Ordering::Relaxed)];
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            termswx.get_switch_tx().send(MsgType::History(tx)).unwrap();
+            if rx.recv_timeout(Duration::from_secs(1)).is_ok_and(|output| ends_with_prompt(&output, &prompts)) {
+                let cmd = stty_command(&status);
+                trace!("Send {}", cmd.trim());
+                for val in cmd.as_bytes() {
+                    termswx.get_switch_tx().send(MsgType::Console(*val)).unwrap();
+                }
+                tracker.sent();
+            }
+        }
+    });
+}
+
+
 // Use the alternate screen for output
-fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<AtomicI8>) {
+fn show_help(fileconfig: &FileConfig, devices: &[ConsoleDevice], active: usize) {
+    let cmdopts = &devices[active].opts;
+    let clients = devices[active].termswx.get_client_table();
+    let scripts = devices[active].termswx.get_scripts();
+    let results = devices[active].termswx.get_script_results();
+    let switch_tx = devices[active].termswx.get_switch_tx();
     terminal::disable_raw_mode().unwrap();
     execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen).unwrap();
     let size = crossterm::terminal::size().unwrap();
@@ -100,6 +455,15 @@ fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<Ato
     println!("{}", format!("  {}: v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
     if cmdopts.portnum > 0 {
         println!("{}", format!("  Server portnumber: {}", cmdopts.portnum));
+        if !cmdopts.address.is_empty() {
+            println!("  Server addresses: {}", listen_text(cmdopts, cmdopts.portnum, &cmdopts.listen));
+        }
+    }
+    if cmdopts.webport > 0 {
+        println!("  Web terminal portnumber: {}", cmdopts.webport);
+        if !cmdopts.address.is_empty() {
+            println!("  Web terminal addresses: {}", listen_text(cmdopts, cmdopts.webport, &cmdopts.weblisten));
+        }
     }
     match realdev {
         Ok(path) => {
@@ -113,7 +477,8 @@ fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<Ato
             println!("{}", format!("  Connected to: {:?}", cmdopts.device));
         }
     }
-    println!("{}", format!("  Remote clients: {} of maximum {}", clients.load(Ordering::Relaxed), cmdopts.maxclients));
+    let connected = clients.lock().unwrap().clone();
+    println!("  Remote clients: {} of maximum {}", connected.len(), cmdopts.maxclients);
     let configfile = cmdopts.config_file.clone().into_os_string().into_string().unwrap();
     println!("{}", format!("  Tracefile: {}", cmdopts.tracefile));
     println!("{}", format!("  Configurationfile: {}", configfile));
@@ -125,6 +490,65 @@ fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<Ato
 
     fileconfig.print_shortcuts();
     println!("  ESC => close this help");
+
+    if devices.len() > 1 {
+        println!("{}", "\n".on(Color::White));
+        println!("{}", "=== Devices".with(Color::White).on(Color::DarkGreen));
+        for (idx, device) in devices.iter().enumerate() {
+            let state = if idx == active {
+                ", active"
+            } else if device.termswx.has_unseen() {
+                ", new output"
+            } else {
+                ""
+            };
+            println!("  {}: {}{}", idx + 1, device_text(&device.opts), state);
+        }
+    }
+
+    let running = scripts.lock().unwrap().clone();
+    if !running.is_empty() {
+        println!("{}", "\n".on(Color::White));
+        println!("{}", "=== Running Scripts".with(Color::White).on(Color::DarkGreen));
+        for (pos, script) in running.iter().enumerate() {
+            let process = if script.pid == 0 { String::from("file transfer") } else { format!("process id {}", script.pid) };
+            println!("  {} => Stop script {}: {} ({}, running {})", pos + 1, script.id, script.arg, process,
+                     show_duration_str(script.start.elapsed().as_secs()));
+        }
+    }
+
+    if !connected.is_empty() {
+        println!("{}", "\n".on(Color::White));
+        println!("{}", "=== Remote Clients".with(Color::White).on(Color::DarkGreen));
+        for (pos, client) in connected.iter().take(MENU_CLIENTS).enumerate() {
+            let lock = if client.write_lock.load(Ordering::Relaxed) { ", write lock" } else { "" };
+            println!("  {} => Disconnect {} ({:?}{}, connected {} for {}, in: {} bytes, out: {} bytes)",
+                     (b'a' + pos as u8) as char, client.addr, client.role, lock,
+                     client.connected.format("%Y-%m-%d %H:%M:%S"), show_duration_str(client.start.elapsed().as_secs()),
+                     client.bytes_in.load(Ordering::Relaxed), client.bytes_out.load(Ordering::Relaxed));
+        }
+        if connected.len() > MENU_CLIENTS {
+            println!("  ... and {} more clients", connected.len() - MENU_CLIENTS);
+        }
+    }
+
+    let finished = results.lock().unwrap().clone();
+    if !finished.is_empty() {
+        println!("{}", "\n".on(Color::White));
+        println!("{}", "=== Finished Scripts".with(Color::White).on(Color::DarkGreen));
+        for result in finished.iter().rev() {
+            let text = format!("  Script {}: {}: {} ({} ago)", result.id, result.arg, result.status,
+                               show_duration_str(result.end.elapsed().as_secs()));
+            if result.success {
+                println!("{}", text);
+            } else {
+                println!("{}", text.with(Color::Red));
+                for line in result.stderr.iter() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
     terminal::enable_raw_mode().unwrap();
     loop {
         let mut buffer = vec![0; 80];
@@ -137,6 +561,25 @@ fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<Ato
         } else {
             if cnt == 1 && buffer[0] == 0x1b {
                 break;
+            }
+            // Stop a running script using its position in the list
+            if cnt == 1 && (b'1'..=b'9').contains(&buffer[0]) && usize::from(buffer[0] - b'1') < running.len() {
+                let script = &running[usize::from(buffer[0] - b'1')];
+                println!("\rStop script {}: {}\r", script.id, script.arg);
+                script.stop();
+            } else if cnt == 1 && buffer[0].is_ascii_lowercase()
+                      && usize::from(buffer[0] - b'a') < connected.len().min(MENU_CLIENTS) {
+                // Disconnect a remote client using its position in the list when the user confirms it
+                let client = &connected[usize::from(buffer[0] - b'a')];
+                print!("\rDisconnect client {}? (y/n) ", client.addr);
+                io::stdout().flush().unwrap();
+                let cnt = io::stdin().read(&mut buffer).unwrap();
+                if cnt == 1 && buffer[0] == b'y' {
+                    println!("yes\r");
+                    switch_tx.send(MsgType::NetClientKick(client.addr)).unwrap();
+                } else {
+                    println!("no\r");
+                }
             } else {
                 println!("\rKeyseq: {}", dump_keyseq(&buffer[0..cnt]));
             }
@@ -146,9 +589,9 @@ fn show_help(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, clients: &Arc<Ato
 }
 
 
-fn build_script_envir(cmdopts: &CmdLineConfig, fileconfig: &FileConfig) -> HashMap<String, String> {
+pub fn build_script_envir(cmdopts: &CmdLineConfig, fileconfig: &FileConfig, results: &ScriptResults) -> HashMap<String, String> {
     // Get Terminal Size into an environment variable
-    let size = format!("{:?}", crossterm::terminal::size().unwrap());
+    let size = format!("{:?}", crossterm::terminal::size().unwrap_or((80, 24)));
     // Get Terminal device into an environment variable
     let device = String::from(cmdopts.device.to_str().unwrap());
     // Get Terminal device baudrate into an environment variable
@@ -157,7 +600,16 @@ fn build_script_envir(cmdopts: &CmdLineConfig, fileconfig: &FileConfig) -> HashM
     let mut envir = HashMap::new();
     envir.insert("TERMSWX_SIZE".to_string(), size);
     envir.insert("TERMSWX_DEV".to_string(), device);
+    envir.insert("TERMSWX_NAME".to_string(), cmdopts.name.clone());
     envir.insert("TERMSWX_BAUDRATE".to_string(), baudrate);
+    // Let a script act on the outcome of the previous script
+    if let Some(result) = results.lock().unwrap().back() {
+        envir.insert("TERMSWX_LAST_SCRIPT".to_string(), result.arg.clone());
+        envir.insert("TERMSWX_LAST_STATUS".to_string(), result.status.clone());
+        if let Some(code) = result.code {
+            envir.insert("TERMSWX_LAST_EXIT".to_string(), code.to_string());
+        }
+    }
     if let Some(mut eiter) = fileconfig.get_enviroment() {
         for (key, value) in eiter.by_ref() {
             envir.insert(key.to_string(), value.to_string());
@@ -169,45 +621,148 @@ fn build_script_envir(cmdopts: &CmdLineConfig, fileconfig: &FileConfig) -> HashM
 
 // Open the local console for reading input.  Handle shortcuts: injecting or starting a script
 // Use the raw mode (no wait for enter, no automatic output)
-pub fn open_console(termswx: &mut TermSwitch, cmdopts: &CmdLineConfig, fileconfig: FileConfig) -> Result<thread::JoinHandle<()>,u32> {
+// The input goes to the active device and only the output of the active device is shown
+pub fn open_console(switches: &[TermSwitch], cmdopts: &[CmdLineConfig], fileconfig: FileConfig) -> Result<thread::JoinHandle<()>,u32> {
     const NL: u8 = 0xa;
     const CR: u8 = 0xd;
-    trace!("Starting console thread: quiet: {}", cmdopts.server);
+    trace!("Starting console thread: quiet: {}", cmdopts[0].server);
     terminal::enable_raw_mode().unwrap();
 
-    banner(&cmdopts, fileconfig.find_command(TermCommand::HelpMenu).expect("Found no helpkey in the configuration"));
+    banner(&cmdopts[0], fileconfig.find_command(TermCommand::HelpMenu).expect("Found no helpkey in the configuration"));
     // Running a silent server -> no keyboard handling except exit
-    if cmdopts.server {
+    if cmdopts[0].server {
+        for termswx in switches.iter() {
+            termswx.hide();
+        }
         return wait_for_exit(fileconfig);
     }
 
-    let switch_tx = termswx.get_switch_tx();
-    let console_rx = termswx.get_console_rx();
-    let script_rx = termswx.get_script_rx();
-    let script_pid = termswx.get_script_pid();
-    let binary_mode = termswx.get_binary_mode();
-    let clients = termswx.get_clients();
+    let devices: Vec<ConsoleDevice> = zip(cmdopts, switches)
+        .map(|(opts, termswx)| ConsoleDevice { opts: opts.clone(), termswx: termswx.clone() })
+        .collect();
+    for device in devices.iter().skip(1) {
+        device.termswx.hide();
+    }
+    if devices.len() > 1 {
+        show_device_status(&devices, 0);
+    }
+    let consoles: Vec<_> = switches.iter().map(|termswx| termswx.get_console_rx()).collect();
+    let view: SharedView = Arc::new(Mutex::new(None));
+    start_render(view.clone());
+    let output_view = view.clone();
+    let status: SharedStatus = Arc::new(Mutex::new(None));
+    start_refresh(status.clone());
+    let output_status = status.clone();
+    // The device that gets the keyboard input
+    let active_device = Arc::new(AtomicUsize::new(0));
+    if fileconfig.get_resize_stty() {
+        let switches = devices.iter().map(|device| device.termswx.clone()).collect();
+        watch_resize(switches, active_device.clone(), status.clone(), view.clone(), fileconfig.get_prompts());
+    }
+
+    let endings = fileconfig.get_line_endings();
+    let output_endings = endings.clone();
 
     // Process keyboard input
-    let thropts = cmdopts.clone();
     let thr = thread::spawn(move || {
         let mut buffer = vec![0; 80];
-        let in_prompt: Arc::<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let mut active = 0;
+        // Id of the script that is waiting for the user to respond to its prompt
+        let in_prompt: Arc::<AtomicU32> = Arc::new(AtomicU32::new(0));
+        // The keyboard input is collected in lines when local line editing is on
+        let mut editor: Option<LineEditor> = None;
         loop {
             let cnt = io::stdin().read(&mut buffer).unwrap();
             trace!(" - chars {}", dump_keyseq(&buffer[0..cnt]));
+            let thropts = &devices[active].opts;
+            let switch_tx = devices[active].termswx.get_switch_tx();
+            let scripts = devices[active].termswx.get_scripts();
+            let results = devices[active].termswx.get_script_results();
+            let clients = devices[active].termswx.get_client_table();
             if let Some(cmd) = fileconfig.find_shortcut(&buffer, cnt) {
                 match cmd {
-                    TermCommand::HelpMenu => show_help(&thropts, &fileconfig, &clients),
+                    TermCommand::HelpMenu => {
+                        let split = pause_split(&view, true);
+                        if !split {
+                            pause_status(&status, true);
+                        }
+                        show_help(&fileconfig, &devices, active);
+                        if split {
+                            execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen).unwrap();
+                            pause_split(&view, false);
+                        } else {
+                            pause_status(&status, false);
+                        }
+                    }
+                    TermCommand::NextDevice => active = step_device(&devices, active, &view, true),
+                    TermCommand::PreviousDevice => active = step_device(&devices, active, &view, false),
+                    TermCommand::SelectDevice(idx) => {
+                        let focused = view.lock().unwrap().as_mut().map(|split| split.focus_device(*idx));
+                        match focused {
+                            Some(true) => active = *idx,
+                            Some(false) => trace!("Device {} is not in the split view", idx + 1),
+                            None => active = select_device(&devices, active, *idx),
+                        }
+                    }
+                    TermCommand::SplitView => toggle_split(&devices, active, &view, &status),
+                    TermCommand::LineMode => {
+                        let text = match editor.take() {
+                            Some(mut lines) => {
+                                write_echo(&status, &lines.discard());
+                                "Local line editing: off"
+                            }
+                            None => {
+                                editor = Some(LineEditor::new(fileconfig.get_line_words(), endings.localecho));
+                                "Local line editing: on"
+                            }
+                        };
+                        println!("\r{}\r", text.with(Color::White).on(Color::DarkBlue));
+                    }
+                    TermCommand::StatusBar => {
+                        let split = view.lock().unwrap().is_some();
+                        toggle_status(&devices, &active_device, &status, split);
+                    }
+                    TermCommand::FocusPane => {
+                        if let Some(split) = view.lock().unwrap().as_mut() {
+                            active = split.move_focus(true);
+                        }
+                    }
                     TermCommand::Nop => (),
                     TermCommand::Quit => {
                         trace!("Console Quit");
+                        if view.lock().unwrap().take().is_some() {
+                            execute!(std::io::stdout(), crossterm::terminal::LeaveAlternateScreen).unwrap();
+                        }
+                        if let Some(mut bar) = status.lock().unwrap().take() {
+                            let _ = bar.hide(&mut io::stdout());
+                        }
                         break;
                     }
                     TermCommand::StopScript => {
-                        trace!("Kill Script with pid: {}", script_pid.load(Ordering::Relaxed));
-                        if script_pid.load(Ordering::Relaxed) != 0 {
-                            signal(script_pid.load(Ordering::Relaxed));
+                        // Stop the most recently started script
+                        if let Some(script) = scripts.lock().unwrap().last() {
+                            trace!("Kill Script {} with pid: {}", script.id, script.pid);
+                            script.stop();
+                        }
+                    }
+                    TermCommand::KickClients => {
+                        for client in clients.lock().unwrap().iter() {
+                            trace!("Kick client {}", client.addr);
+                            switch_tx.send(MsgType::NetClientKick(client.addr)).unwrap();
+                        }
+                    }
+                    TermCommand::KickClient(arg) => {
+                        // Match the address with or without the port number
+                        let matching: Vec<_> = clients.lock().unwrap().iter()
+                            .filter(|client| client.addr.to_string() == *arg || client.addr.ip().to_string() == *arg)
+                            .map(|client| client.addr)
+                            .collect();
+                        if matching.is_empty() {
+                            println!("\r{}\r", format!("No remote client at {}", arg).with(Color::Red));
+                        }
+                        for addr in matching {
+                            trace!("Kick client {}", addr);
+                            switch_tx.send(MsgType::NetClientKick(addr)).unwrap();
                         }
                     }
                     TermCommand::SerialBreak => {
@@ -215,17 +770,26 @@ pub fn open_console(termswx: &mut TermSwitch, cmdopts: &CmdLineConfig, fileconfi
                         switch_tx.send(MsgType::SerialBreak).unwrap();
                     }
                     TermCommand::Inject(seq) => {
+                        // The text is edited like typed text in the line editing mode
+                        let seq = match editor.as_mut() {
+                            Some(lines) => {
+                                let edit = lines.feed(seq);
+                                write_echo(&status, &edit.echo);
+                                edit.send
+                            }
+                            None => seq.clone(),
+                        };
                         for val in seq {
-                            switch_tx.send(MsgType::Console(*val)).unwrap();
+                            switch_tx.send(MsgType::Console(val)).unwrap();
                         }
                     }
                     TermCommand::Prompt(arg) => {
                         println!("{}\r", format!("{}", arg).with(Color::White).on(Color::DarkGreen));
                     }
                     TermCommand::FileInject(arg) => {
-                        let pid = script_pid.load(Ordering::Relaxed);
-                        if pid != 0 {
-                            println!("{}", format!("Error: Script with PID {:?} already running", pid).with(Color::Red));
+                        let running = scripts.lock().unwrap().len();
+                        if running != 0 {
+                            println!("{}", format!("Error: {} script(s) already running", running).with(Color::Red));
                         } else {
                             // Replace "~" with the home folder for script paths
                             let narg = subst_home(arg);
@@ -244,26 +808,70 @@ pub fn open_console(termswx: &mut TermSwitch, cmdopts: &CmdLineConfig, fileconfi
                         }
                     }
                     TermCommand::RunScript(arg) => {
-                        let pid = script_pid.load(Ordering::Relaxed);
-                        if pid != 0 {
-                            println!("{}", format!("Error: Script with PID {:?} already running", pid).with(Color::Red));
+                        let running = scripts.lock().unwrap().len();
+                        if running >= fileconfig.get_maxscripts() {
+                            println!("{}", format!("Error: Maximum of {} scripts already running", running).with(Color::Red));
                         } else {
                             let cmd = ScriptCommand {
                                 tx: switch_tx.clone(),
-                                rx: script_rx.clone(),
-                                pid: script_pid.clone(),
+                                scripts: scripts.clone(),
+                                results: results.clone(),
                                 arg: arg.to_string().clone(),
                                 python: fileconfig.get_python(),
-                                envir: build_script_envir(&thropts, &fileconfig),
+                                envir: build_script_envir(thropts, &fileconfig, &results),
                                 in_prompt: in_prompt.clone(),
-                                binary_mode: binary_mode.clone(),
+                                timeouts: fileconfig.get_script_timeouts(arg),
+                            };
+                            let _ = execute_script(cmd);
+                        }
+                    }
+                    TermCommand::SendFile(protocol, arg) | TermCommand::ReceiveFile(protocol, arg) => {
+                        let running = scripts.lock().unwrap().len();
+                        if running >= fileconfig.get_maxscripts() {
+                            println!("{}", format!("Error: Maximum of {} scripts already running", running).with(Color::Red));
+                        } else {
+                            let cmd = TransferCommand {
+                                tx: switch_tx.clone(),
+                                scripts: scripts.clone(),
+                                protocol: *protocol,
+                                send: matches!(cmd, TermCommand::SendFile(..)),
+                                path: arg.clone(),
                             };
-                            execute_script(cmd);
+                            start_transfer(cmd);
+                        }
+                    }
+                    TermCommand::Upload(local, remote) => {
+                        let running = scripts.lock().unwrap().len();
+                        if running >= fileconfig.get_maxscripts() {
+                            println!("{}", format!("Error: Maximum of {} scripts already running", running).with(Color::Red));
+                        } else {
+                            let cmd = UploadCommand {
+                                tx: switch_tx.clone(),
+                                scripts: scripts.clone(),
+                                local: local.clone(),
+                                remote: remote.clone(),
+                                settings: fileconfig.get_shell_transfer(),
+                            };
+                            start_upload(cmd);
+                        }
+                    }
+                    TermCommand::Download(remote, local) => {
+                        let running = scripts.lock().unwrap().len();
+                        if running >= fileconfig.get_maxscripts() {
+                            println!("{}", format!("Error: Maximum of {} scripts already running", running).with(Color::Red));
+                        } else {
+                            let cmd = DownloadCommand {
+                                tx: switch_tx.clone(),
+                                scripts: scripts.clone(),
+                                remote: remote.clone(),
+                                local: local.clone(),
+                                settings: fileconfig.get_shell_transfer(),
+                            };
+                            start_download(cmd);
                         }
                     }
                     TermCommand::SttySize => {
-                        let size = terminal::size().unwrap();
-                        let cmd = format!("stty cols {} rows {}\r", size.0 - 1, size.1 - 1);
+                        let cmd = stty_command(&status);
                         for val in cmd.as_bytes() {
                             switch_tx.send(MsgType::Console(*val)).unwrap();
                         }
@@ -279,72 +887,113 @@ pub fn open_console(termswx: &mut TermSwitch, cmdopts: &CmdLineConfig, fileconfi
                             }
                         }
                     }
+                    TermCommand::TakeControl => {
+                        if thropts.networkdev {
+                            // The server handles the key for its remote clients
+                            for val in buffer[0..cnt].iter() {
+                                switch_tx.send(MsgType::Console(*val)).unwrap();
+                            }
+                        } else {
+                            switch_tx.send(MsgType::TakeControl(None)).unwrap();
+                        }
+                    }
                 }
+                active_device.store(active, Ordering::Relaxed);
             } else {
-                if in_prompt.load(Ordering::Relaxed) {
+                let prompt_id = in_prompt.load(Ordering::Relaxed);
+                // The prompt can come from a script on another device than the active one
+                let prompt_tx = devices.iter()
+                    .find(|device| device.termswx.get_scripts().lock().unwrap().iter().any(|elem| elem.id == prompt_id))
+                    .map(|device| device.termswx.get_switch_tx());
+                if prompt_id != 0 && prompt_tx.is_none() {
+                    in_prompt.store(0, Ordering::Relaxed);
+                }
+                if let Some(switch_tx) = prompt_tx {
                     for idx in 0..cnt {
                         let val: u8 = buffer[idx];
 
-                        switch_tx.send(MsgType::ScriptAlertResponse(val)).unwrap();
+                        switch_tx.send(MsgType::ScriptAlertResponse(prompt_id, val)).unwrap();
                         let out: &[u8] = &buffer[idx..idx+1];
                         io::stdout().write(out).unwrap();
                         io::stdout().flush().unwrap();
                         if val == CR {
-                            in_prompt.store(false, Ordering::Relaxed);
+                            in_prompt.store(0, Ordering::Relaxed);
                         }
                     }
+                } else if let Some(lines) = editor.as_mut() {
+                    let edit = lines.feed(&buffer[0..cnt]);
+                    write_echo(&status, &edit.echo);
+                    send_input(&switch_tx, &edit.send, &endings, &status, false);
                 } else {
-                    for idx in 0..cnt {
-                        let val: u8 = buffer[idx];
-                        match switch_tx.send(MsgType::Console(val)) {
-                            Ok(_) => (),
-                            Err(_) => {
-                                error!("Cannot send console input to term_switch");
-                            }
-                        }
-                    }
+                    send_input(&switch_tx, &buffer[0..cnt], &endings, &status, true);
                 }
             }
         }
     });
 
     // Send responses to stdout (eg echo from the serial port)
-    thread::spawn(move || {
-        let mut buffer = vec![0; 2];
-        loop {
-            match console_rx.recv() {
-                Ok(MsgType::Console(ch)) => {
-                    #[cfg(target_os = "windows")]
-                    if ch >= 0x80 {
+    for (idx, console_rx) in consoles.into_iter().enumerate() {
+        let view = output_view.clone();
+        let status = output_status.clone();
+        let endings = output_endings.clone();
+        thread::spawn(move || console_output(console_rx, idx, view, status, endings));
+    }
+    Ok(thr)
+}
+
+
+// The output goes to the pane of the device when the split view is shown
+fn console_output(console_rx: Receiver<MsgType>, device: usize, view: SharedView, status: SharedStatus,
+                  endings: LineEndings) {
+    let mut previous = 0;
+    loop {
+        match console_rx.recv() {
+            Ok(MsgType::Console(ch)) => {
+                #[cfg(target_os = "windows")]
+                if ch >= 0x80 {
+                    continue;
+                }
+                let buffer = translate_output(ch, previous, &endings);
+                previous = ch;
+                for ch in buffer {
+                    if view.lock().unwrap().as_mut().is_some_and(|split| split.push(device, ch)) {
                         continue;
                     }
-                    buffer.clear();
-                    buffer.push(ch);
-                    match io::stdout().write(&buffer) {
+                    // The status bar is drawn between the escape sequences of the output
+                    let mut status = status.lock().unwrap();
+                    if let Some(bar) = status.as_mut() {
+                        bar.output(ch);
+                    }
+                    match io::stdout().write(&[ch]) {
                         Ok(_) => (),
                         Err(e) => {
                             error!("Receive Console Error: {e:?}");
                         }
                     }
                 }
-                Ok(MsgType::Exit) => {
-                    trace!("Console Exit received");
-                    break;
-                }
-                Ok(_) => (),
-                Err(e) => {
-                    error!("Receive Error: {e:?}");
-                }
             }
-            match io::stdout().flush() {
-                Ok(_) => (),
-                Err(e) => {
-                    error!("Flush Error: {e:?}");
-                }
+            Ok(MsgType::Exit) => {
+                trace!("Console Exit received");
+                break;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                error!("Receive Error: {e:?}");
             }
         }
-    });
-    Ok(thr)
+        match io::stdout().flush() {
+            Ok(_) => (),
+            Err(e) => {
+                error!("Flush Error: {e:?}");
+            }
+        }
+    }
+}
+
+
+// Raw mode and the banner need a terminal on both stdin and stdout
+pub fn is_terminal() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
 }
 
 
@@ -352,3 +1001,62 @@ pub fn open_console(termswx: &mut TermSwitch, cmdopts: &CmdLineConfig, fileconfi
 pub fn close_console() {
     terminal::disable_raw_mode().unwrap();
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts() {
+        let prompts = vec![String::from("$ "), String::from("# ")];
+        assert!(ends_with_prompt(b"ls\r\nbin etc\r\n\x1b[?2004hroot@board:~# ", &prompts));
+        assert!(ends_with_prompt(b"user@host:~$ ", &prompts));
+        assert!(!ends_with_prompt(b"# editing in vi\r\n~\r\n~", &prompts));
+        assert!(!ends_with_prompt(b"login: ", &prompts));
+        assert!(!ends_with_prompt(b"", &[String::new()]));
+    }
+
+    #[test]
+    fn resize() {
+        let mut tracker = ResizeTracker::new((80, 24), 23);
+        assert!(!tracker.update((80, 24), 23));
+        // Sent once the new size has been seen twice
+        assert!(!tracker.update((120, 40), 39));
+        assert!(!tracker.update((132, 43), 42));
+        assert!(tracker.update((132, 43), 42));
+        // Retried until the device shows a prompt
+        assert!(tracker.update((132, 43), 42));
+        tracker.sent();
+        assert!(!tracker.update((132, 43), 42));
+        // The status bar was hidden
+        assert!(!tracker.update((132, 43), 43));
+        assert!(tracker.update((132, 43), 43));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resize_signal() {
+        catch_resize();
+        let start = Instant::now();
+        unsafe { libc::raise(libc::SIGWINCH) };
+        wait_resize();
+        assert!(start.elapsed() < RESIZE_INTERVAL);
+        assert!(!RESIZED.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut endings = LineEndings { localecho: false, enter: b"\r\n".to_vec(), lftocrlf: false, stripcr: false };
+        assert_eq!(translate_enter(b"ls\r", &endings), b"ls\r\n");
+        assert_eq!(local_echo(b"ab\x7f\x03\r"), b"ab\x08 \x08\r\n");
+        assert_eq!(local_echo(b"\x1b[A"), b"");
+        assert_eq!(translate_output(b'\n', b'a', &endings), b"\n");
+        endings.lftocrlf = true;
+        assert_eq!(translate_output(b'\n', b'a', &endings), b"\r\n");
+        assert_eq!(translate_output(b'\n', b'\r', &endings), b"\n");
+        endings.stripcr = true;
+        assert_eq!(translate_output(b'\r', b'a', &endings), b"");
+        assert_eq!(translate_output(b'\n', b'\r', &endings), b"\r\n");
+    }
+}
@@ -0,0 +1,359 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:55
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Control a running termswx via a Unix domain socket
+// A client sends one command line and gets a status line ("ok" or "error: <reason>") followed by the reply text
+
+use crate::CmdLineConfig;
+use crate::config::{FileConfig, subst_home, unescape};
+use crate::console_service::build_script_envir;
+use crate::script_runner::{ScriptCommand, execute_script};
+use crate::term_switch::{TermSwitch, MsgType};
+
+use log::{error, trace};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, atomic::AtomicU32, atomic::Ordering};
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel::{bounded, Sender};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[derive(Debug, PartialEq)]
+enum ControlCommand {
+    Status,
+    Clients,
+    Break,
+    Baudrate(u32),
+    LogStart(String),
+    LogStop,
+    Run(String),
+    Inject(Vec<u8>),
+    Output(Option<usize>),
+    Quit,
+}
+
+
+// A device and its term switch as seen from the control socket
+struct ControlDevice {
+    opts: CmdLineConfig,
+    termswx: TermSwitch,
+    // Loaded at start: the file is not read again while the instance runs
+    fileconfig: Arc<FileConfig>,
+    // The folder termswx was started in: a daemon runs in the root folder
+    folder: PathBuf,
+}
+
+
+// A command can start with @<number|name> to select a device: the first device is the default
+fn parse_command(line: &str) -> Result<(Option<String>, ControlCommand), String> {
+    let mut line = line.trim();
+    let mut device = None;
+    if let Some(rest) = line.strip_prefix('@') {
+        let (name, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        device = Some(String::from(name));
+        line = rest.trim();
+    }
+    let (cmd, arg) = line.split_once(' ').map_or((line, ""), |(cmd, arg)| (cmd, arg.trim()));
+    let command = match (cmd, arg) {
+        ("status", "") => ControlCommand::Status,
+        ("clients", "") => ControlCommand::Clients,
+        ("break", "") => ControlCommand::Break,
+        ("baudrate", rate) => match rate.parse::<u32>() {
+            Ok(rate) if rate > 0 => ControlCommand::Baudrate(rate),
+            _ => return Err(format!("Invalid baudrate: {}", rate)),
+        },
+        ("log", "stop") => ControlCommand::LogStop,
+        ("log", arg) if arg.starts_with("start ") => ControlCommand::LogStart(String::from(arg[6..].trim())),
+        ("run", args) if !args.is_empty() => ControlCommand::Run(String::from(args)),
+        ("inject", text) if !text.is_empty() => ControlCommand::Inject(unescape(text)?),
+        ("output", "") => ControlCommand::Output(None),
+        ("output", size) => match size.parse::<usize>() {
+            Ok(size) => ControlCommand::Output(Some(size)),
+            _ => return Err(format!("Invalid output size: {}", size)),
+        },
+        ("quit", "") => ControlCommand::Quit,
+        _ => return Err(format!("Unknown command: {}", line)),
+    };
+    Ok((device, command))
+}
+
+
+// Find a device by its number (counting from 1) or its name
+fn find_device(devices: &[ControlDevice], name: &str) -> Result<usize, String> {
+    if let Ok(number) = name.parse::<usize>() {
+        if number > 0 && number <= devices.len() {
+            return Ok(number - 1);
+        }
+    }
+    devices.iter().position(|device| device.opts.name == name).ok_or(format!("No device {}", name))
+}
+
+
+fn status_text(idx: usize, device: &ControlDevice) -> String {
+    let state = device.termswx.get_state();
+    let scripts: Vec<String> = device.termswx.get_scripts().lock().unwrap().iter()
+        .map(|script| script.arg.clone())
+        .collect();
+    let mut text = format!("device: {} {}\n", idx + 1, device.opts.name);
+    text.push_str(&format!("path: {}\n", device.opts.device.display()));
+    let baudrate = state.baudrate.load(Ordering::Relaxed);
+    if baudrate > 0 {
+        text.push_str(&format!("baudrate: {}\n", baudrate));
+    }
+    let connected = if state.connected.load(Ordering::Relaxed) { "connected" } else { "disconnected" };
+    text.push_str(&format!("state: {}\n", connected));
+    text.push_str(&format!("clients: {}\n", device.termswx.get_clients().load(Ordering::Relaxed)));
+    text.push_str(&format!("scripts: {}\n", if scripts.is_empty() { String::from("none") } else { scripts.join(", ") }));
+    text.push_str(&format!("log: {}\n", state.log_path().unwrap_or(String::from("off"))));
+    text.push_str(&format!("received: {}\n", state.received.load(Ordering::Relaxed)));
+    text.push_str(&format!("sent: {}\n", state.sent.load(Ordering::Relaxed)));
+    text
+}
+
+
+fn clients_text(device: &ControlDevice) -> String {
+    let mut text = String::new();
+    for client in device.termswx.get_client_table().lock().unwrap().iter() {
+        let lock = if client.write_lock.load(Ordering::Relaxed) { " write lock" } else { "" };
+        text.push_str(&format!("{} {:?} connected {} in {} out {}{}\n", client.addr, client.role,
+                               client.connected.format("%Y-%m-%d %H:%M:%S"), client.bytes_in.load(Ordering::Relaxed),
+                               client.bytes_out.load(Ordering::Relaxed), lock));
+    }
+    text
+}
+
+
+// Relative log and script paths are given from the folder termswx was started in
+fn resolve_path(folder: &Path, path: &str) -> String {
+    if path.starts_with('~') {
+        return subst_home(&String::from(path));
+    }
+    if path.is_empty() || Path::new(path).is_absolute() {
+        return String::from(path);
+    }
+    folder.join(path).to_string_lossy().to_string()
+}
+
+
+fn run_script(device: &ControlDevice, arg: &str) -> Result<String, String> {
+    let arg = match arg.split_once(' ') {
+        Some((script, args)) => format!("{} {}", resolve_path(&device.folder, script), args),
+        None => resolve_path(&device.folder, arg),
+    };
+    let opts = &device.opts;
+    let fileconfig = &device.fileconfig;
+    let scripts = device.termswx.get_scripts();
+    let running = scripts.lock().unwrap().len();
+    if running >= fileconfig.get_maxscripts() {
+        return Err(format!("Maximum of {} scripts already running", running));
+    }
+    let results = device.termswx.get_script_results();
+    let cmd = ScriptCommand {
+        tx: device.termswx.get_switch_tx(),
+        scripts,
+        envir: build_script_envir(opts, fileconfig, &results),
+        results,
+        python: fileconfig.get_python(),
+        // Nobody answers a script prompt here
+        in_prompt: Arc::new(AtomicU32::new(0)),
+        timeouts: fileconfig.get_script_timeouts(&arg),
+        arg: arg.clone(),
+    };
+    execute_script(cmd).map_err(|e| format!("Could not start script {}: {}", arg, e))?;
+    Ok(format!("Started script: {}\n", arg))
+}
+
+
+// The recent output kept in the history of the term switch
+fn output_data(device: &ControlDevice, size: Option<usize>) -> Result<Vec<u8>, String> {
+    let (tx, rx) = bounded(1);
+    device.termswx.get_switch_tx().send(MsgType::History(tx)).unwrap();
+    let data = rx.recv_timeout(REQUEST_TIMEOUT).map_err(|_| String::from("No reply from the device"))?;
+    match size {
+        Some(size) if size < data.len() => Ok(data[data.len() - size..].to_vec()),
+        _ => Ok(data),
+    }
+}
+
+
+fn execute(devices: &[ControlDevice], selector: Option<String>, command: &ControlCommand) -> Result<Vec<u8>, String> {
+    let idx = match &selector {
+        Some(name) => find_device(devices, name)?,
+        None => 0,
+    };
+    let device = &devices[idx];
+    match command {
+        ControlCommand::Status if selector.is_none() => {
+            let text: Vec<String> = devices.iter().enumerate().map(|(idx, device)| status_text(idx, device)).collect();
+            Ok(text.join("\n").into_bytes())
+        }
+        ControlCommand::Status => Ok(status_text(idx, device).into_bytes()),
+        ControlCommand::Clients => Ok(clients_text(device).into_bytes()),
+        ControlCommand::Break => {
+            device.termswx.get_switch_tx().send(MsgType::SerialBreak).unwrap();
+            Ok(Vec::new())
+        }
+        ControlCommand::Baudrate(rate) => {
+            if device.opts.networkdev {
+                return Err(format!("{} is a network connection", device.opts.name));
+            }
+            device.termswx.get_switch_tx().send(MsgType::SerialBaudrate(*rate)).unwrap();
+            Ok(Vec::new())
+        }
+        ControlCommand::LogStart(path) => {
+            device.termswx.get_state().start_log(&resolve_path(&device.folder, path))?;
+            Ok(Vec::new())
+        }
+        ControlCommand::LogStop => {
+            device.termswx.get_state().stop_log();
+            Ok(Vec::new())
+        }
+        ControlCommand::Run(arg) => run_script(device, arg).map(String::into_bytes),
+        ControlCommand::Inject(text) => {
+            let tx = device.termswx.get_switch_tx();
+            for ch in text {
+                tx.send(MsgType::Console(*ch)).unwrap();
+            }
+            Ok(Vec::new())
+        }
+        ControlCommand::Output(size) => output_data(device, *size),
+        ControlCommand::Quit => Ok(Vec::new()),
+    }
+}
+
+
+fn serve_control(mut stream: UnixStream, devices: &[ControlDevice], quit_tx: &Sender<()>) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        error!("Control request error: {}", e);
+        return;
+    }
+    trace!("Control: {}", line.trim());
+    let mut quit = false;
+    let reply = match parse_command(&line) {
+        Ok((selector, command)) => {
+            quit = command == ControlCommand::Quit;
+            execute(devices, selector, &command)
+        }
+        Err(e) => Err(e),
+    };
+    // The output command can return binary data so the reply is sent as bytes
+    let reply = match reply {
+        Ok(data) => [b"ok\n".to_vec(), data].concat(),
+        Err(e) => format!("error: {}\n", e).into_bytes(),
+    };
+    let _ = stream.write_all(&reply);
+    drop(stream);
+    if quit {
+        quit_tx.send(()).unwrap();
+    }
+}
+
+
+// Create the socket before the process detaches so errors are shown to the user
+pub fn bind_control(path: &str) -> Result<UnixListener, String> {
+    if Path::new(path).exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("The control socket {} is in use", path));
+        }
+        // Left behind by an instance that did not stop cleanly
+        let _ = std::fs::remove_file(path);
+    }
+    // Only the owner can control the instance: the socket is created with mode 0600
+    let mask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(mask) };
+    listener.map_err(|e| format!("Control socket {}: {}", path, e))
+}
+
+
+pub fn start_control(listener: UnixListener, devices: &[CmdLineConfig], switches: &[TermSwitch], fileconfig: FileConfig,
+                     folder: PathBuf, quit_tx: Sender<()>) {
+    let fileconfig = Arc::new(fileconfig);
+    let devices: Arc<Vec<ControlDevice>> = Arc::new(devices.iter().zip(switches.iter())
+        .map(|(opts, termswx)| ControlDevice { opts: opts.clone(), termswx: termswx.clone(),
+                                               fileconfig: fileconfig.clone(), folder: folder.clone() })
+        .collect());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let devices = devices.clone();
+                    let quit_tx = quit_tx.clone();
+                    thread::spawn(move || serve_control(stream, &devices, &quit_tx));
+                }
+                Err(e) => error!("Control socket error: {}", e),
+            }
+        }
+    });
+}
+
+
+// Send one command to a running instance and copy the reply to stdout: returns the exit code
+pub fn control_client(socket: &str, command: &[String]) -> i32 {
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Control socket {}: {}", socket, e);
+            return 2;
+        }
+    };
+    let line = format!("{}\n", command.join(" "));
+    let mut reply = Vec::new();
+    if let Err(e) = stream.write_all(line.as_bytes()).and_then(|_| stream.read_to_end(&mut reply)) {
+        eprintln!("Control socket {}: {}", socket, e);
+        return 2;
+    }
+    match reply.strip_prefix(b"ok\n") {
+        Some(data) => {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(data).and_then(|_| stdout.flush());
+            0
+        }
+        None => {
+            eprint!("{}", String::from_utf8_lossy(&reply));
+            1
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command("status\n"), Ok((None, ControlCommand::Status)));
+        assert_eq!(parse_command("@board2 baudrate 9600"),
+                   Ok((Some(String::from("board2")), ControlCommand::Baudrate(9600))));
+        assert_eq!(parse_command("@2 log start /tmp/board2.log"),
+                   Ok((Some(String::from("2")), ControlCommand::LogStart(String::from("/tmp/board2.log")))));
+        assert_eq!(parse_command("log stop"), Ok((None, ControlCommand::LogStop)));
+        assert_eq!(parse_command("run test.py --count 2"), Ok((None, ControlCommand::Run(String::from("test.py --count 2")))));
+        assert!(parse_command("baudrate fast").is_err());
+        assert!(parse_command("run").is_err());
+        assert!(parse_command("reboot").is_err());
+        assert_eq!(parse_command("@1 inject reboot\\r"), Ok((Some(String::from("1")), ControlCommand::Inject(b"reboot\r".to_vec()))));
+        assert_eq!(parse_command("output"), Ok((None, ControlCommand::Output(None))));
+        assert_eq!(parse_command("output 200"), Ok((None, ControlCommand::Output(Some(200)))));
+        assert!(parse_command("output all").is_err());
+        assert!(parse_command("inject").is_err());
+    }
+
+    #[test]
+    fn paths() {
+        let folder = Path::new("/home/user/board");
+        assert_eq!(resolve_path(folder, "board.log"), "/home/user/board/board.log");
+        assert_eq!(resolve_path(folder, "logs/../board.log"), "/home/user/board/logs/../board.log");
+        assert_eq!(resolve_path(folder, "/tmp/board.log"), "/tmp/board.log");
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(resolve_path(folder, "~/board.log"), format!("{}/board.log", home));
+    }
+}
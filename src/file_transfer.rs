@@ -0,0 +1,321 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:36
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Send and receive files over the device connection using the built-in file transfer protocols.
+// The transfer registers with the term switch like a script in binary mode so that it gets the
+// device output for itself and nothing is echoed on the console.
+
+use crate::term_switch::{MsgType, register_script};
+use crate::config::{subst_home, TransferProtocol};
+use crate::console_service::show_error;
+use crate::script_runner::{ScriptInfo, ScriptTable};
+use crate::{kermit, xmodem, zmodem};
+
+use log::{error, info};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, atomic::AtomicBool, atomic::Ordering};
+use crossbeam_channel::{Sender, Receiver, RecvTimeoutError};
+use crossterm::style::{Color, Stylize};
+
+
+// Byte stream to the device used by the file transfer protocols
+pub trait TransferPort {
+    // Read a byte from the device: None if nothing arrived within the timeout
+    fn read(&mut self, timeout: Duration) -> Option<u8>;
+
+    // Write bytes to the device
+    fn write(&mut self, data: &[u8]);
+
+    // Drop what the device has sent until the line is quiet
+    fn purge(&mut self) {
+        while self.read(Duration::from_millis(100)).is_some() {}
+    }
+
+    // The user has stopped the transfer
+    fn cancelled(&self) -> bool {
+        false
+    }
+
+    // Show how much of a file has been transferred
+    fn progress(&mut self, _name: &str, _done: u64, _total: Option<u64>) {}
+}
+
+
+// A file that is sent or has been received: the name is empty if the protocol does not provide it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileData {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+
+pub struct TransferCommand {
+    pub tx: Sender<MsgType>,
+    pub scripts: ScriptTable,
+    pub protocol: TransferProtocol,
+    pub send: bool,
+    pub path: String,
+}
+
+
+// The device connection via the term switch
+struct SwitchPort {
+    id: u32,
+    tx: Sender<MsgType>,
+    rx: Receiver<MsgType>,
+    cancel: Arc<AtomicBool>,
+    start: Instant,
+    shown: Option<Instant>,
+}
+
+impl TransferPort for SwitchPort {
+    fn read(&mut self, timeout: Duration) -> Option<u8> {
+        let end = Instant::now() + timeout;
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            let slice = end.saturating_duration_since(Instant::now()).min(Duration::from_millis(100));
+            match self.rx.recv_timeout(slice) {
+                Ok(MsgType::Console(ch)) => return Some(ch),
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => {
+                    if Instant::now() >= end {
+                        return None;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        for val in data {
+            self.tx.send(MsgType::ScriptOutput(self.id, *val)).unwrap();
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Update the progress bar at most 5 times per second
+    fn progress(&mut self, name: &str, done: u64, total: Option<u64>) {
+        if self.shown.is_some_and(|shown| shown.elapsed() < Duration::from_millis(200)) && Some(done) != total {
+            return;
+        }
+        self.shown = Some(Instant::now());
+        let rate = done as f64 / self.start.elapsed().as_secs_f64().max(0.001) / 1024.0;
+        let text = match total {
+            Some(total) if total > 0 => {
+                let percent = (done.min(total) * 100 / total) as usize;
+                format!("{} [{}{}] {:3}% {}/{} bytes {:.1} kB/s", name, "#".repeat(percent / 4),
+                        " ".repeat(25 - percent / 4), percent, done, total, rate)
+            }
+            _ => format!("{} {} bytes {:.1} kB/s", name, done, rate),
+        };
+        print!("\r{}\x1b[K", text.with(Color::Black).on(Color::DarkYellow));
+        io::stdout().flush().unwrap();
+    }
+}
+
+
+fn protocol_name(protocol: TransferProtocol) -> &'static str {
+    match protocol {
+        TransferProtocol::Xmodem => "XMODEM",
+        TransferProtocol::Xmodem1k => "XMODEM-1K",
+        TransferProtocol::Ymodem => "YMODEM",
+        TransferProtocol::Zmodem => "ZMODEM",
+        TransferProtocol::Kermit => "Kermit",
+    }
+}
+
+
+// Only use the last part of a filename provided by the sender
+fn safe_filename(name: &str) -> Option<&str> {
+    Path::new(name).file_name().and_then(|name| name.to_str())
+}
+
+
+// Store received files: a directory path uses the names provided by the sender
+fn save_files(path: &str, files: &[FileData]) -> Result<Vec<PathBuf>, String> {
+    let target = PathBuf::from(path);
+    let mut saved = Vec::new();
+    for (idx, file) in files.iter().enumerate() {
+        let filename = if target.is_dir() {
+            match safe_filename(&file.name) {
+                Some(name) => target.join(name),
+                None => return Err(format!("Missing a filename for {}", path)),
+            }
+        } else if idx == 0 {
+            target.clone()
+        } else {
+            match safe_filename(&file.name) {
+                Some(name) => target.with_file_name(name),
+                None => return Err(format!("Missing a filename for file {} of the batch", idx + 1)),
+            }
+        };
+        std::fs::write(&filename, &file.data).map_err(|e| format!("Could not write {:?}: {}", filename, e))?;
+        saved.push(filename);
+    }
+    Ok(saved)
+}
+
+
+fn run_transfer(port: &mut dyn TransferPort, protocol: TransferProtocol, path: &str, file: Option<FileData>)
+                -> Result<String, String> {
+    if let Some(file) = file {
+        let size = file.data.len();
+        match protocol {
+            TransferProtocol::Xmodem => xmodem::send(port, &file, false)?,
+            TransferProtocol::Xmodem1k => xmodem::send(port, &file, true)?,
+            TransferProtocol::Ymodem => xmodem::send_batch(port, &[file])?,
+            TransferProtocol::Zmodem => zmodem::send(port, &[file])?,
+            TransferProtocol::Kermit => kermit::send(port, &[file])?,
+        }
+        return Ok(format!("Sent {} ({} bytes)", path, size));
+    }
+    let files = match protocol {
+        TransferProtocol::Xmodem | TransferProtocol::Xmodem1k => vec![xmodem::receive(port)?],
+        TransferProtocol::Ymodem => xmodem::receive_batch(port)?,
+        TransferProtocol::Zmodem => zmodem::receive(port)?,
+        TransferProtocol::Kermit => kermit::receive(port)?,
+    };
+    let saved = save_files(path, &files)?;
+    let names: Vec<String> = saved.iter().map(|name| format!("{:?}", name)).collect();
+    Ok(format!("Received {}", names.join(", ")))
+}
+
+
+// Start a file transfer in its own thread
+pub fn start_transfer(cmd: TransferCommand) {
+    let name = protocol_name(cmd.protocol);
+    let path = subst_home(&cmd.path);
+    let arg = format!("{} {} {}", name, if cmd.send { "send" } else { "receive" }, path);
+
+    let file = if cmd.send {
+        match std::fs::read(&path) {
+            Ok(data) => {
+                let filename = safe_filename(&path).unwrap_or_default().to_string();
+                Some(FileData { name: filename, data })
+            }
+            Err(e) => {
+                show_error(vec![format!("Could not read file \"{}\"", path), format!("Error: \"{}\"", e)]);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let protocol = cmd.protocol;
+    spawn_transfer(cmd.tx, cmd.scripts, arg, move |port| {
+        run_transfer(port, protocol, &path, file).map(|text| format!("{}: {}", name, text))
+    });
+}
+
+
+// Register a transfer with the term switch as a script in binary mode and run it in its own thread
+pub fn spawn_transfer<F>(tx: Sender<MsgType>, scripts: ScriptTable, arg: String, job: F)
+    where F: FnOnce(&mut dyn TransferPort) -> Result<String, String> + Send + 'static {
+    // Register the transfer with the term switch to get its own channel
+    let (id, rx) = match register_script(&tx) {
+        Ok(script) => script,
+        Err(e) => {
+            error!("Could not register transfer: {e}");
+            return;
+        }
+    };
+    let cancel = Arc::new(AtomicBool::new(false));
+    scripts.lock().unwrap().push(ScriptInfo {
+        id,
+        pid: 0,
+        arg: arg.clone(),
+        start: Instant::now(),
+        cancel: Some(cancel.clone()),
+    });
+    tx.send(MsgType::ScriptBinary(id, true)).unwrap();
+    let text = format!("Start {} as script {}", arg, id);
+    println!("\r{}\r", text);
+    info!("{}", text);
+
+    thread::spawn(move || {
+        let mut port = SwitchPort {
+            id,
+            tx: tx.clone(),
+            rx,
+            cancel,
+            start: Instant::now(),
+            shown: None,
+        };
+        let res = job(&mut port);
+        tx.send(MsgType::ScriptBinary(id, false)).unwrap();
+        tx.send(MsgType::ScriptDone(id)).unwrap();
+        scripts.lock().unwrap().retain(|elem| elem.id != id);
+        println!();
+        match res {
+            Ok(text) => {
+                info!("{}", text);
+                println!("\r{}\r", text.with(Color::White).on(Color::DarkGreen));
+            }
+            Err(e) => {
+                error!("{} failed: {}", arg, e);
+                show_error(vec![format!("{} failed", arg), format!("Error: {}", e)]);
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    // One end of an in-memory connection between a sender and a receiver
+    pub struct LoopPort {
+        pub tx: Sender<u8>,
+        pub rx: Receiver<u8>,
+        // Corrupt the written byte at this position (counted from the start)
+        pub corrupt: Option<usize>,
+        pub written: usize,
+    }
+
+    impl TransferPort for LoopPort {
+        fn read(&mut self, timeout: Duration) -> Option<u8> {
+            self.rx.recv_timeout(timeout).ok()
+        }
+
+        fn write(&mut self, data: &[u8]) {
+            for val in data {
+                let val = if self.corrupt == Some(self.written) { val ^ 0x55 } else { *val };
+                self.written += 1;
+                let _ = self.tx.send(val);
+            }
+        }
+    }
+
+    // Create a connected pair of ports
+    pub fn loop_ports() -> (LoopPort, LoopPort) {
+        let (a_tx, a_rx) = unbounded();
+        let (b_tx, b_rx) = unbounded();
+        (LoopPort { tx: a_tx, rx: b_rx, corrupt: None, written: 0 },
+         LoopPort { tx: b_tx, rx: a_rx, corrupt: None, written: 0 })
+    }
+
+    // File content that includes all byte values
+    pub fn test_data(size: usize) -> Vec<u8> {
+        (0..size).map(|idx| (idx * 7 + idx / 256) as u8).collect()
+    }
+
+    #[test]
+    fn filename_without_path() {
+        assert_eq!(safe_filename("/etc/passwd"), Some("passwd"));
+        assert_eq!(safe_filename("../image.bin"), Some("image.bin"));
+        assert_eq!(safe_filename(""), None);
+    }
+}
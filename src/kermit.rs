@@ -0,0 +1,480 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 18:10
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Kermit file transfer with short packets and single character checksums as used by bootloaders
+// like the U-Boot loadb command.  Repeat counts and 8th bit prefixing are used when the other
+// side asks for them.
+
+use crate::file_transfer::{TransferPort, FileData};
+use crate::xmodem::CANCELLED;
+
+use log::{info, trace};
+use std::iter;
+use std::time::{Duration, Instant};
+
+const SOH: u8 = 0x01;
+const CR: u8 = 0x0d;
+const QCTL: u8 = b'#';
+const REPT: u8 = b'~';
+// Longest packet that we want to receive
+const MAXL: u8 = 94;
+const RETRIES: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(5);
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+
+fn tochar(val: u8) -> u8 {
+    val + 32
+}
+
+
+fn unchar(val: u8) -> u8 {
+    val.wrapping_sub(32)
+}
+
+
+fn ctl(val: u8) -> u8 {
+    val ^ 64
+}
+
+
+// Block check type 1: a 6 bit checksum of the packet after the mark
+fn checksum(data: &[u8]) -> u8 {
+    let sum: u32 = data.iter().map(|val| u32::from(*val)).sum();
+    tochar(((sum + ((sum & 0xc0) >> 6)) & 0x3f) as u8)
+}
+
+
+// The parameters that we send in the Send-Init packet and its acknowledge
+fn init_data() -> Vec<u8> {
+    vec![tochar(MAXL), tochar(TIMEOUT.as_secs() as u8), tochar(0), ctl(0), tochar(CR), QCTL, b'Y', b'1', REPT]
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Coder {
+    // Prefix used by the other side for control characters
+    qctl: u8,
+    qbin: Option<u8>,
+    rept: Option<u8>,
+}
+
+impl Coder {
+    fn encode_byte(&self, mut val: u8, out: &mut Vec<u8>) {
+        if let Some(qbin) = self.qbin {
+            if val & 0x80 != 0 {
+                out.push(qbin);
+                val &= 0x7f;
+            }
+        }
+        let low = val & 0x7f;
+        if low < 32 || low == 127 {
+            out.extend_from_slice(&[QCTL, ctl(val)]);
+        } else if low == QCTL || Some(low) == self.qbin || Some(low) == self.rept {
+            out.extend_from_slice(&[QCTL, val]);
+        } else {
+            out.push(val);
+        }
+    }
+
+    // Encode data from a position until the room in a packet is used
+    fn encode(&self, data: &[u8], pos: &mut usize, room: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(room);
+        while *pos < data.len() {
+            let val = data[*pos];
+            let mut count = 1;
+            let mut item = Vec::with_capacity(5);
+            if let Some(rept) = self.rept {
+                while count < 94 && *pos + count < data.len() && data[*pos + count] == val {
+                    count += 1;
+                }
+                if count >= 3 {
+                    item.extend_from_slice(&[rept, tochar(count as u8)]);
+                } else {
+                    count = 1;
+                }
+            }
+            self.encode_byte(val, &mut item);
+            if out.len() + item.len() > room {
+                break;
+            }
+            out.extend_from_slice(&item);
+            *pos += count;
+        }
+        out
+    }
+
+    fn decode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut iter = data.iter().copied();
+        while let Some(mut val) = iter.next() {
+            let mut count = 1;
+            if Some(val) == self.rept {
+                count = usize::from(unchar(iter.next().unwrap_or(b' ')));
+                val = iter.next().unwrap_or_default();
+            }
+            let mut high = 0;
+            if Some(val) == self.qbin {
+                high = 0x80;
+                val = iter.next().unwrap_or_default();
+            }
+            if val == self.qctl {
+                val = iter.next().unwrap_or_default();
+                if (0x3f..=0x5f).contains(&(val & 0x7f)) {
+                    val = ctl(val);
+                }
+            }
+            out.extend(iter::repeat_n(val | high, count));
+        }
+        out
+    }
+}
+
+
+#[derive(Debug)]
+struct Packet {
+    seq: u8,
+    kind: u8,
+    data: Vec<u8>,
+}
+
+
+// State of a transfer: the settings are updated by the Send-Init exchange
+struct Session {
+    seq: u8,
+    maxl: usize,
+    eol: u8,
+    coder: Coder,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            seq: 0,
+            maxl: 80,
+            eol: CR,
+            coder: Coder { qctl: QCTL, qbin: None, rept: None },
+        }
+    }
+
+    // Use the parameters from the Send-Init packet of the other side
+    fn negotiate(&mut self, data: &[u8]) {
+        if let Some(maxl) = data.first() {
+            self.maxl = usize::from(unchar(*maxl)).clamp(20, usize::from(MAXL));
+        }
+        if let Some(eol) = data.get(4) {
+            self.eol = unchar(*eol);
+        }
+        if let Some(qctl) = data.get(5) {
+            self.coder.qctl = *qctl;
+        }
+        // 8th bit prefixing is only used when the other side asks for it with a prefix character
+        self.coder.qbin = data.get(6).copied().filter(|val| matches!(val, 33..=62 | 96..=126));
+        self.coder.rept = data.get(8).copied().filter(|val| *val == REPT);
+        trace!("Kermit parameters: maxl {} eol {:#02x} {:?}", self.maxl, self.eol, self.coder);
+    }
+
+    fn packet(&self, seq: u8, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![SOH, tochar((data.len() + 3) as u8), tochar(seq), kind];
+        packet.extend_from_slice(data);
+        let check = checksum(&packet[1..]);
+        packet.extend_from_slice(&[check, self.eol]);
+        packet
+    }
+
+    fn message(&self, packet: &Packet) -> String {
+        String::from_utf8_lossy(&self.coder.decode(&packet.data)).to_string()
+    }
+
+    // Tell the other side why the transfer stopped
+    fn error(&self, port: &mut dyn TransferPort, text: &str) -> String {
+        let mut pos = 0;
+        let data = self.coder.encode(text.as_bytes(), &mut pos, self.maxl - 3);
+        port.write(&self.packet(self.seq, b'E', &data));
+        String::from(text)
+    }
+
+    // Send a packet until it is acknowledged and return the data in the acknowledge
+    fn send_packet(&mut self, port: &mut dyn TransferPort, kind: u8, data: &[u8], retries: usize)
+                   -> Result<Vec<u8>, String> {
+        let packet = self.packet(self.seq, kind, data);
+        let next = (self.seq + 1) % 64;
+        for _ in 0..retries {
+            if port.cancelled() {
+                return Err(self.error(port, CANCELLED));
+            }
+            port.write(&packet);
+            match read_packet(port, TIMEOUT) {
+                Ok(reply) if reply.kind == b'Y' && reply.seq == self.seq => {
+                    self.seq = next;
+                    return Ok(reply.data);
+                }
+                // A NAK for the next packet also acknowledges this packet
+                Ok(reply) if reply.kind == b'N' && reply.seq == next => {
+                    self.seq = next;
+                    return Ok(Vec::new());
+                }
+                Ok(reply) if reply.kind == b'E' => {
+                    return Err(format!("The receiver stopped the transfer: {}", self.message(&reply)));
+                }
+                _ => trace!("Resend packet {} type {}", self.seq, kind as char),
+            }
+        }
+        Err(self.error(port, &format!("Packet {} was not acknowledged", self.seq)))
+    }
+}
+
+
+// Read the next packet: bytes before the mark are skipped
+fn read_packet(port: &mut dyn TransferPort, timeout: Duration) -> Result<Packet, String> {
+    let start = Instant::now();
+    let mut buffer: Option<Vec<u8>> = None;
+    loop {
+        let Some(val) = port.read(timeout.saturating_sub(start.elapsed())) else {
+            return Err(String::from("Timeout"));
+        };
+        if val == SOH {
+            buffer = Some(Vec::new());
+            continue;
+        }
+        let Some(packet) = buffer.as_mut() else {
+            continue;
+        };
+        packet.push(val);
+        let len = usize::from(unchar(packet[0]));
+        if !(3..=usize::from(MAXL)).contains(&len) {
+            return Err(format!("Bad packet length {}", len));
+        }
+        if packet.len() == len + 1 {
+            if checksum(&packet[..len]) != packet[len] {
+                return Err(String::from("Bad checksum"));
+            }
+            return Ok(Packet {
+                seq: unchar(packet[1]) % 64,
+                kind: packet[2],
+                data: packet[3..len].to_vec(),
+            });
+        }
+    }
+}
+
+
+// File attributes: only the size in bytes is provided
+fn attributes(size: usize) -> Vec<u8> {
+    let size = size.to_string();
+    let mut data = vec![b'1', tochar(size.len() as u8)];
+    data.extend_from_slice(size.as_bytes());
+    data
+}
+
+
+fn parse_size(data: &[u8]) -> Option<u64> {
+    let mut pos = 0;
+    while pos + 1 < data.len() {
+        let len = usize::from(unchar(data[pos + 1]));
+        let value = data.get(pos + 2..pos + 2 + len)?;
+        if data[pos] == b'1' {
+            return String::from_utf8_lossy(value).parse().ok();
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+
+// Send files with Kermit
+pub fn send(port: &mut dyn TransferPort, files: &[FileData]) -> Result<(), String> {
+    let mut session = Session::new();
+    let retries = (START_TIMEOUT.as_secs() / TIMEOUT.as_secs()) as usize;
+    let params = session.send_packet(port, b'S', &init_data(), retries)?;
+    session.negotiate(&params);
+    for file in files {
+        let total = file.data.len() as u64;
+        let mut pos = 0;
+        let name = session.coder.encode(file.name.as_bytes(), &mut pos, session.maxl - 3);
+        session.send_packet(port, b'F', &name, RETRIES)?;
+        if session.send_packet(port, b'A', &attributes(file.data.len()), RETRIES)?.first() == Some(&b'N') {
+            info!("Kermit receiver refused {}", file.name);
+            session.send_packet(port, b'Z', b"D", RETRIES)?;
+            continue;
+        }
+        let mut pos = 0;
+        port.progress(&file.name, 0, Some(total));
+        while pos < file.data.len() {
+            let data = session.coder.encode(&file.data, &mut pos, session.maxl - 3);
+            let reply = session.send_packet(port, b'D', &data, RETRIES)?;
+            if matches!(reply.first(), Some(b'X') | Some(b'Z')) {
+                session.send_packet(port, b'Z', b"D", RETRIES)?;
+                return Err(String::from("Cancelled by the receiver"));
+            }
+            port.progress(&file.name, pos as u64, Some(total));
+        }
+        session.send_packet(port, b'Z', &[], RETRIES)?;
+    }
+    session.send_packet(port, b'B', &[], RETRIES)?;
+    Ok(())
+}
+
+
+// Receive files with Kermit until the sender ends the batch
+pub fn receive(port: &mut dyn TransferPort) -> Result<Vec<FileData>, String> {
+    let mut session = Session::new();
+    let mut files = Vec::new();
+    let mut current: Option<(FileData, Option<u64>)> = None;
+    let mut last_ack = session.packet(0, b'N', &[]);
+    let start = Instant::now();
+    let mut started = false;
+    let mut errors = 0;
+    loop {
+        if port.cancelled() {
+            return Err(session.error(port, CANCELLED));
+        }
+        let packet = match read_packet(port, TIMEOUT) {
+            Ok(packet) => packet,
+            Err(e) => {
+                trace!("Kermit receive: {}", e);
+                if !started && start.elapsed() > START_TIMEOUT {
+                    return Err(session.error(port, "The sender did not start"));
+                }
+                if started {
+                    errors += 1;
+                    if errors >= RETRIES {
+                        return Err(session.error(port, "Too many errors"));
+                    }
+                }
+                port.write(&session.packet(session.seq, b'N', &[]));
+                continue;
+            }
+        };
+        if started && packet.seq == (session.seq + 63) % 64 {
+            // The sender did not get the acknowledge
+            port.write(&last_ack);
+            continue;
+        }
+        if packet.seq != session.seq || (!started && packet.kind != b'S') {
+            port.write(&session.packet(session.seq, b'N', &[]));
+            continue;
+        }
+        errors = 0;
+        let reply = match packet.kind {
+            b'S' => {
+                session.negotiate(&packet.data);
+                started = true;
+                init_data()
+            }
+            b'F' => {
+                let name = session.message(&packet);
+                info!("Kermit receive {}", name);
+                current = Some((FileData { name, data: Vec::new() }, None));
+                Vec::new()
+            }
+            b'A' => {
+                if let Some((_, size)) = current.as_mut() {
+                    *size = parse_size(&packet.data);
+                }
+                Vec::new()
+            }
+            b'D' => {
+                let Some((file, size)) = current.as_mut() else {
+                    return Err(session.error(port, "Data without a file header"));
+                };
+                file.data.extend(session.coder.decode(&packet.data));
+                port.progress(&file.name, file.data.len() as u64, *size);
+                Vec::new()
+            }
+            b'Z' => {
+                // The sender may ask to discard the file
+                if let Some((file, _)) = current.take() {
+                    if session.message(&packet) != "D" {
+                        files.push(file);
+                    }
+                }
+                Vec::new()
+            }
+            b'B' => Vec::new(),
+            b'E' => return Err(format!("The sender stopped the transfer: {}", session.message(&packet))),
+            kind => return Err(session.error(port, &format!("Unsupported packet type {}", kind as char))),
+        };
+        last_ack = session.packet(session.seq, b'Y', &reply);
+        port.write(&last_ack);
+        session.seq = (session.seq + 1) % 64;
+        if packet.kind == b'B' {
+            return Ok(files);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::{loop_ports, test_data};
+    use std::thread;
+
+    #[test]
+    fn encode_decode() {
+        let data: Vec<u8> = (0..=255).chain([0x41; 20]).chain([b'#', b'~', b'&', 0xff, 0xff]).collect();
+        for coder in [
+            Coder { qctl: QCTL, qbin: None, rept: None },
+            Coder { qctl: QCTL, qbin: Some(b'&'), rept: Some(REPT) },
+        ] {
+            let mut pos = 0;
+            let mut decoded = Vec::new();
+            while pos < data.len() {
+                let encoded = coder.encode(&data, &mut pos, 91);
+                assert!(encoded.len() <= 91);
+                if coder.qbin.is_some() {
+                    assert!(encoded.iter().all(|val| (32..127).contains(val)));
+                }
+                decoded.extend(coder.decode(&encoded));
+            }
+            assert_eq!(decoded, data);
+        }
+    }
+
+    #[test]
+    fn packet_checksum() {
+        // The Send-Init packet from the Kermit protocol manual
+        let session = Session::new();
+        let packet = session.packet(0, b'S', b"~* @-#Y3~^>J)0___F\"U1@");
+        assert_eq!(packet[packet.len() - 2], checksum(&packet[1..packet.len() - 2]));
+        let (mut sender, mut receiver) = loop_ports();
+        sender.write(&packet);
+        let packet = read_packet(&mut receiver, TIMEOUT).unwrap();
+        assert_eq!((packet.seq, packet.kind), (0, b'S'));
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let (mut sender, mut receiver) = loop_ports();
+        let files = vec![
+            FileData { name: String::from("u-boot.bin"), data: test_data(5000) },
+            FileData { name: String::from("zeros.bin"), data: vec![0; 1000] },
+        ];
+        let expected = files.clone();
+        let thr = thread::spawn(move || send(&mut sender, &files));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn resend_after_corruption() {
+        let (mut sender, mut receiver) = loop_ports();
+        sender.corrupt = Some(1000);
+        receiver.corrupt = Some(200);
+        let files = vec![FileData { name: String::from("test.bin"), data: test_data(3000) }];
+        let expected = files.clone();
+        let thr = thread::spawn(move || send(&mut sender, &files));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn file_size_attribute() {
+        assert_eq!(parse_size(&attributes(123456)), Some(123456));
+        assert_eq!(parse_size(b"#\"AB1\"42"), Some(42));
+    }
+}
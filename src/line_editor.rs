@@ -0,0 +1,395 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:58
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Collect a line locally with editing and history before sending it to the device
+// The line is shown after the cursor position where editing started, and is removed again when it is sent so
+// the device echo shows it, unless the device does not echo
+
+use std::collections::VecDeque;
+
+const HISTORY_SIZE: usize = 100;
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+
+// The result of the keyboard input: text for the terminal and bytes for the device
+#[derive(Debug, Default, PartialEq)]
+pub struct LineEdit {
+    pub echo: Vec<u8>,
+    pub send: Vec<u8>,
+}
+
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Complete,
+    ClearLine,
+    ClearToEnd,
+    Control(u8),
+    Ignore,
+}
+
+
+// Split the keyboard input in keys: the length of an incomplete UTF-8 character is returned as None
+fn next_key(input: &[u8]) -> Option<(Key, usize)> {
+    let key = match input[0] {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Complete,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x15 => Key::ClearLine,
+        0x0b => Key::ClearToEnd,
+        ESC => {
+            // Only CSI and SS3 sequences are keys: the rest is ignored
+            let (key, len) = match input.get(1) {
+                Some(b'[') => {
+                    let end = input[2..].iter().position(|ch| (0x40..=0x7e).contains(ch)).map(|pos| pos + 3);
+                    match end {
+                        Some(end) => (match &input[2..end] {
+                            b"A" => Key::Up,
+                            b"B" => Key::Down,
+                            b"C" => Key::Right,
+                            b"D" => Key::Left,
+                            b"H" | b"1~" | b"7~" => Key::Home,
+                            b"F" | b"4~" | b"8~" => Key::End,
+                            b"3~" => Key::Delete,
+                            _ => Key::Ignore,
+                        }, end),
+                        None => (Key::Ignore, input.len()),
+                    }
+                }
+                Some(b'O') => (match input.get(2) {
+                    Some(b'A') => Key::Up,
+                    Some(b'B') => Key::Down,
+                    Some(b'C') => Key::Right,
+                    Some(b'D') => Key::Left,
+                    Some(b'H') => Key::Home,
+                    Some(b'F') => Key::End,
+                    _ => Key::Ignore,
+                }, input.len().min(3)),
+                _ => (Key::Ignore, 1),
+            };
+            return Some((key, len));
+        }
+        ch if ch < 0x20 => Key::Control(ch),
+        ch => {
+            let len = match ch {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            if input.len() < len {
+                return None;
+            }
+            return match std::str::from_utf8(&input[..len]) {
+                Ok(text) => Some((Key::Char(text.chars().next().unwrap()), len)),
+                Err(_) => Some((Key::Ignore, 1)),
+            };
+        }
+    };
+    Some((key, 1))
+}
+
+
+fn move_left(echo: &mut Vec<u8>, count: usize) {
+    if count > 0 {
+        echo.extend_from_slice(format!("\x1b[{}D", count).as_bytes());
+    }
+}
+
+
+fn common_prefix(words: &[&String]) -> String {
+    let mut prefix: Vec<char> = words[0].chars().collect();
+    for word in words.iter().skip(1) {
+        let len = prefix.iter().zip(word.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
+
+
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    // Position in the history while recalling lines and the line that was being typed
+    recall: Option<usize>,
+    draft: String,
+    words: Vec<String>,
+    // Start of a UTF-8 character from the previous input
+    pending: Vec<u8>,
+    // Leave a sent line on the terminal for devices that do not echo
+    keep: bool,
+}
+
+impl LineEditor {
+    pub fn new(words: Vec<String>, keep: bool) -> LineEditor {
+        LineEditor {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            recall: None,
+            draft: String::new(),
+            words,
+            pending: Vec::new(),
+            keep,
+        }
+    }
+
+    pub fn feed(&mut self, input: &[u8]) -> LineEdit {
+        let mut edit = LineEdit::default();
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(input);
+        let mut pos = 0;
+        while pos < data.len() {
+            match next_key(&data[pos..]) {
+                Some((key, len)) => {
+                    self.key(key, &mut edit);
+                    pos += len;
+                }
+                None => {
+                    self.pending = data[pos..].to_vec();
+                    break;
+                }
+            }
+        }
+        edit
+    }
+
+    // Remove the line from the terminal and forget it
+    pub fn discard(&mut self) -> Vec<u8> {
+        let mut echo = Vec::new();
+        move_left(&mut echo, self.cursor);
+        echo.extend_from_slice(b"\x1b[K");
+        self.line.clear();
+        self.cursor = 0;
+        self.recall = None;
+        echo
+    }
+
+    fn text(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    fn set_line(&mut self, text: &str) {
+        self.line = text.chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    // Draw the line again: the cursor was at the old position
+    fn redraw(&self, old_cursor: usize, edit: &mut LineEdit) {
+        move_left(&mut edit.echo, old_cursor);
+        edit.echo.extend_from_slice(self.text().as_bytes());
+        edit.echo.extend_from_slice(b"\x1b[K");
+        move_left(&mut edit.echo, self.line.len() - self.cursor);
+    }
+
+    fn key(&mut self, key: Key, edit: &mut LineEdit) {
+        let old_cursor = self.cursor;
+        match key {
+            Key::Char(ch) => {
+                self.line.insert(self.cursor, ch);
+                self.cursor += 1;
+            }
+            Key::Enter => {
+                let text = self.text();
+                if !text.is_empty() && self.history.back() != Some(&text) {
+                    if self.history.len() >= HISTORY_SIZE {
+                        self.history.pop_front();
+                    }
+                    self.history.push_back(text.clone());
+                }
+                if self.keep {
+                    let rest = self.line.len() - self.cursor;
+                    if rest > 0 {
+                        edit.echo.extend_from_slice(format!("\x1b[{}C", rest).as_bytes());
+                    }
+                    edit.echo.extend_from_slice(b"\r\n");
+                    self.line.clear();
+                    self.cursor = 0;
+                    self.recall = None;
+                } else {
+                    edit.echo.extend(self.discard());
+                }
+                edit.send.extend_from_slice(text.as_bytes());
+                edit.send.push(b'\r');
+                return;
+            }
+            Key::Control(ch) => {
+                // Ctrl+C and the like go to the device right away and the line is dropped
+                edit.echo.extend(self.discard());
+                edit.send.push(ch);
+                return;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+            }
+            Key::Left if self.cursor > 0 => self.cursor -= 1,
+            Key::Right if self.cursor < self.line.len() => self.cursor += 1,
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::ClearLine => {
+                self.line.clear();
+                self.cursor = 0;
+            }
+            Key::ClearToEnd => self.line.truncate(self.cursor),
+            Key::Up => {
+                let recall = match self.recall {
+                    None if !self.history.is_empty() => {
+                        self.draft = self.text();
+                        self.history.len() - 1
+                    }
+                    Some(idx) if idx > 0 => idx - 1,
+                    _ => {
+                        edit.echo.push(BEL);
+                        return;
+                    }
+                };
+                self.recall = Some(recall);
+                let text = self.history[recall].clone();
+                self.set_line(&text);
+            }
+            Key::Down => match self.recall {
+                Some(idx) if idx + 1 < self.history.len() => {
+                    self.recall = Some(idx + 1);
+                    let text = self.history[idx + 1].clone();
+                    self.set_line(&text);
+                }
+                Some(_) => {
+                    self.recall = None;
+                    let text = std::mem::take(&mut self.draft);
+                    self.set_line(&text);
+                }
+                None => {
+                    edit.echo.push(BEL);
+                    return;
+                }
+            },
+            Key::Complete => {
+                if !self.complete() {
+                    edit.echo.push(BEL);
+                    return;
+                }
+            }
+            Key::Backspace | Key::Delete | Key::Left | Key::Right | Key::Ignore => return,
+        }
+        self.redraw(old_cursor, edit);
+    }
+
+    // Complete the word before the cursor from the wordlist: a single match is followed by a space
+    fn complete(&mut self) -> bool {
+        let start = self.line[..self.cursor].iter().rposition(|ch| *ch == ' ').map_or(0, |pos| pos + 1);
+        let word: String = self.line[start..self.cursor].iter().collect();
+        let matches: Vec<&String> = self.words.iter().filter(|candidate| candidate.starts_with(&word)).collect();
+        if matches.is_empty() {
+            return false;
+        }
+        let mut insert: Vec<char> = common_prefix(&matches).chars().skip(word.chars().count()).collect();
+        if matches.len() == 1 {
+            insert.push(' ');
+        }
+        if insert.is_empty() {
+            return false;
+        }
+        for ch in insert {
+            self.line.insert(self.cursor, ch);
+            self.cursor += 1;
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(editor: &mut LineEditor, input: &[u8]) -> Vec<u8> {
+        editor.feed(input).send
+    }
+
+    #[test]
+    fn editing() {
+        let mut editor = LineEditor::new(Vec::new(), false);
+        let edit = editor.feed(b"ab");
+        assert_eq!(edit, LineEdit { echo: b"a\x1b[K\x1b[1Dab\x1b[K".to_vec(), send: vec![] });
+        assert_eq!(editor.feed(b"\x1b[D").echo, b"\x1b[2Dab\x1b[K\x1b[1D");
+        editor.feed(b"\x1bOC\x1bOD");
+        editor.feed(b"X\x1b[3~");
+        assert_eq!(editor.text(), "aX");
+        editor.feed(b"\x1b[H\x7f\x7fZ\x1b[F!");
+        assert_eq!(editor.text(), "ZaX!");
+        assert_eq!(sent(&mut editor, b"\r"), b"ZaX!\r");
+        assert_eq!(editor.text(), "");
+        // An UTF-8 character split over two reads
+        editor.feed(b"\xc3");
+        editor.feed(b"\xa6\x15ls");
+        assert_eq!(sent(&mut editor, b"\r"), b"ls\r");
+        editor.feed(b"sleep 10");
+        assert_eq!(sent(&mut editor, b"\x03"), b"\x03");
+        assert_eq!(editor.text(), "");
+    }
+
+    #[test]
+    fn local_echo() {
+        // The line is erased for the device echo, or kept when the device does not echo
+        let mut editor = LineEditor::new(Vec::new(), false);
+        editor.feed(b"ls\x1b[D");
+        assert_eq!(editor.feed(b"\r"), LineEdit { echo: b"\x1b[1D\x1b[K".to_vec(), send: b"ls\r".to_vec() });
+        let mut editor = LineEditor::new(Vec::new(), true);
+        editor.feed(b"ls\x1b[D");
+        assert_eq!(editor.feed(b"\r"), LineEdit { echo: b"\x1b[1C\r\n".to_vec(), send: b"ls\r".to_vec() });
+        assert_eq!(editor.feed(b"pwd\r").echo, b"p\x1b[K\x1b[1Dpw\x1b[K\x1b[2Dpwd\x1b[K\r\n");
+    }
+
+    #[test]
+    fn history() {
+        let mut editor = LineEditor::new(Vec::new(), false);
+        assert_eq!(editor.feed(b"\x1b[A").echo, vec![BEL]);
+        editor.feed(b"printenv\rversion\r");
+        editor.feed(b"boot");
+        editor.feed(b"\x1b[A");
+        assert_eq!(editor.text(), "version");
+        editor.feed(b"\x1b[A\x1b[A");
+        assert_eq!(editor.text(), "printenv");
+        editor.feed(b"\x1b[B\x1b[B");
+        assert_eq!(editor.text(), "boot");
+        editor.feed(b"\x1b[A");
+        assert_eq!(sent(&mut editor, b"\r"), b"version\r");
+        // The same line twice is only kept once
+        assert_eq!(editor.history.len(), 2);
+    }
+
+    #[test]
+    fn completion() {
+        let words = ["setenv", "saveenv", "printenv", "bootm"].map(String::from).to_vec();
+        let mut editor = LineEditor::new(words, false);
+        editor.feed(b"b\t");
+        assert_eq!(editor.text(), "bootm ");
+        editor.feed(b"\x15s\t");
+        assert_eq!(editor.text(), "s");
+        assert_eq!(editor.feed(b"\t").echo, vec![BEL]);
+        editor.feed(b"e\t");
+        assert_eq!(editor.text(), "setenv ");
+        editor.feed(b"pr\t");
+        assert_eq!(editor.text(), "setenv printenv ");
+        assert_eq!(editor.feed(b"x\t").echo.last(), Some(&BEL));
+    }
+}
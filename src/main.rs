@@ -1,46 +1,82 @@
 //Steen Hegelund
-//Time-Stamp: 2026-Jan-31 12:24
+//Time-Stamp: 2026-Oct-18 19:56
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 
 // Configuration file stored as ~/.config/termswx/config.toml
 //
 // Command Line Arguments:
-// - Connect console to a device for read/write: <devicepath|host:port>
+// - Connect console to a device for read/write: <devicepath|host:port|profile>...
 // - Run TCP Server for local device: -p <port>
+// - Run a web terminal for local device: -w <port>
 // - Run quiet TCP Server: -s
+// - Use TLS for the TCP Server or the connection to a server: --tls
 // - Start trace logging at loglevel: -v[v*] <filepath>
 // - Specify tracefile name: -t <filepath>
+// - Store the output of each device in a folder: -l <folder>
+// - Run without a terminal in the background: --daemon --pidfile <filepath>
+// - Accept commands on a Unix domain socket: --control <socketpath>
 
-use log::{info, trace};
+use log::{error, info, trace};
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::fs::canonicalize;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::net::SocketAddr;
 use std::time::Instant;
 use std::env;
 use bpaf::*;
-use config::FileConfig;
+use config::{FileConfig, TermCommand};
+use term_switch::TermSwitch;
+use batch_service::BatchStep;
 
 mod logger_service;
 mod console_service;
 mod script_runner;
 mod serial_service;
 mod network_service;
+mod tls_service;
+mod web_service;
 mod term_switch;
+mod batch_service;
+#[cfg(unix)]
+mod control_service;
+mod split_view;
+mod status_bar;
+mod line_editor;
 mod ansi_filter;
 mod config;
+mod file_transfer;
+mod xmodem;
+mod zmodem;
+mod kermit;
+mod shell_transfer;
 
 const CONFIG_VERSION: i64 = 7;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct CmdLineConfig {
+    devices: Vec<String>,
     device: PathBuf,
+    name: String,
+    logfile: String,
+    logdir: String,
     baudrate: u32,
     portnum: u16,
+    address: Vec<String>,
+    listen: Vec<SocketAddr>,
+    webport: u16,
+    weblisten: Vec<SocketAddr>,
     maxclients: i8,
     server: bool,
+    daemon: bool,
+    pidfile: String,
+    control: String,
+    batch: bool,
+    steps: Vec<BatchStep>,
+    timeout: u64,
+    tls: bool,
     tracefile: String,
     keeprunning: bool,
     enumerate: bool,
@@ -53,11 +89,34 @@ pub struct CmdLineConfig {
 }
 
 
-fn parse_args() -> OptionParser<CmdLineConfig> {
-    let device = positional::<PathBuf>("DEVICE/HOST")
-        .help("Device path /dev/xxx or hostname:portnum")
+// Either run the terminal or send a command to a running instance
+#[derive(Debug, Clone)]
+enum Invocation {
+    Terminal(Box<CmdLineConfig>),
+    Control(String, Vec<String>),
+}
+
+
+fn ctl_command() -> impl Parser<Invocation> {
+    let socket = positional::<String>("SOCKET")
+        .help("The control socket of the running termswx")
+        .complete_shell(bpaf::ShellComp::File { mask: None });
+    let command = positional::<String>("COMMAND")
+        .help("The command and its arguments: put -- in front of arguments starting with -")
+        .some("A command is needed");
+    construct!(Invocation::Control(socket, command))
+        .to_options()
+        .descr("Send a command to a running termswx via its control socket")
+        .command("ctl")
+        .help("Send a command to a running termswx via its control socket")
+}
+
+
+fn parse_args() -> OptionParser<Invocation> {
+    let devices = positional::<String>("DEVICE/HOST")
+        .help("Device path /dev/xxx, hostname:portnum or a device profile\n Several devices can be given")
         .complete_shell(bpaf::ShellComp::File { mask: None })
-        .fallback("".into());
+        .many();
 
     let baudrate = short('b')
         .long("baudrate")
@@ -73,6 +132,19 @@ fn parse_args() -> OptionParser<CmdLineConfig> {
         .guard(|&p| p > 1024, "PORTNUM must be greater than 1024")
         .fallback(0);
 
+    let webport = short('w')
+        .long("webport")
+        .help("Run a web terminal listening on port")
+        .argument::<u16>("WEBPORT")
+        .guard(|&p| p > 1024, "WEBPORT must be greater than 1024")
+        .fallback(0);
+
+    let address = short('a')
+        .long("address")
+        .help("Listen on an IP address or all addresses of an interface (needs -p or -w)\n Can be used several times, default: 0.0.0.0")
+        .argument::<String>("ADDRESS")
+        .many();
+
     let maxclients = short('m')
         .long("maxclients")
         .help("Maximum number of remote clients")
@@ -90,6 +162,51 @@ fn parse_args() -> OptionParser<CmdLineConfig> {
         .help("Activate quiet TCP Server mode (needs -p)")
         .switch();
 
+    let daemon = long("daemon")
+        .help("Run in the background without a terminal")
+        .switch();
+
+    let pidfile = long("pidfile")
+        .help("Write the process id of the daemon to a file")
+        .argument::<String>("FILENAME")
+        .fallback(String::new());
+
+    let control = long("control")
+        .help("Accept commands on a Unix domain socket")
+        .argument::<String>("SOCKET")
+        .fallback(String::new());
+
+    let batch = long("batch")
+        .help("Run the --send, --expect and --run steps without a terminal and exit")
+        .switch();
+
+    let send = long("send")
+        .help("Send text to the device: \\r \\n \\t \\e \\\\ and \\xNN can be used")
+        .argument::<String>("TEXT")
+        .parse(|text| config::unescape(&text).map(BatchStep::Send));
+
+    let expect = long("expect")
+        .help("Wait for the device to output text: escapes as for --send")
+        .argument::<String>("TEXT")
+        .parse(|text| config::unescape(&text).map(BatchStep::Expect));
+
+    let run = long("run")
+        .help("Run a python3 script and wait for it to complete")
+        .argument::<String>("SCRIPT")
+        .map(BatchStep::Run);
+
+    let steps = construct!([send, expect, run]).many();
+
+    let timeout = long("timeout")
+        .help("Seconds to wait for the text of an --expect step or for a --run script")
+        .argument::<u64>("SECONDS")
+        .fallback(10)
+        .display_fallback();
+
+    let tls = long("tls")
+        .help("Use TLS for the TCP Server or the connection to a server\n Certificates are set in the configuration file")
+        .switch();
+
     // Log Level error (highest priority), warn, info, debug, and trace
     let verbose = short('v')
         .long("verbose")
@@ -105,6 +222,12 @@ fn parse_args() -> OptionParser<CmdLineConfig> {
         .argument::<String>("FILENAME")
         .fallback("/tmp/termswx_trace.log".to_string());
 
+    let logdir = short('l')
+        .long("logdir")
+        .help("Store the output of each device in FOLDER/<name>.log")
+        .argument::<String>("FOLDER")
+        .fallback(String::new());
+
     let enumerate = short('e')
         .long("enumerate")
         .help("List available serial ports")
@@ -115,7 +238,12 @@ fn parse_args() -> OptionParser<CmdLineConfig> {
         .help("Show version information")
         .switch();
 
+    let device = pure(PathBuf::new());
+    let name = pure(String::new());
+    let logfile = pure(String::new());
     let networkdev = pure(false);
+    let listen = pure(Vec::new());
+    let weblisten = pure(Vec::new());
     let start = pure(Instant::now());
     let config_version = pure(CONFIG_VERSION);
 
@@ -135,23 +263,39 @@ fn parse_args() -> OptionParser<CmdLineConfig> {
     filename.push("config.toml");
     let config_file = pure(filename);
 
-    construct!(CmdLineConfig {
+    let terminal = construct!(CmdLineConfig {
         baudrate,
         portnum,
+        webport,
+        address,
         maxclients,
         server,
+        daemon,
+        pidfile,
+        control,
+        batch,
+        steps,
+        timeout,
+        tls,
         keeprunning,
         tracefile,
+        logdir,
         verbose,
         enumerate,
         version,
+        devices,
         device,
+        name,
+        logfile,
         networkdev,
+        listen,
+        weblisten,
         start,
         config_version,
         config_file,
-    })
-    .to_options().descr("TermSWX - The Serial Terminal Switch")
+    }).map(|cmdopts| Invocation::Terminal(Box::new(cmdopts)));
+    let ctl = ctl_command();
+    construct!([ctl, terminal]).to_options().descr("TermSWX - The Serial Terminal Switch")
 }
 
 
@@ -183,6 +327,89 @@ fn parse_path(opts: &mut CmdLineConfig) -> bool {
 }
 
 
+// The settings for each device: a profile overrides the command line, and the server ports are
+// counted up from the command line ports for the following devices
+fn device_options(cmdopts: &CmdLineConfig, fileconfig: &FileConfig) -> Result<Vec<CmdLineConfig>, String> {
+    let mut devices = Vec::new();
+    for (idx, arg) in cmdopts.devices.iter().enumerate() {
+        let mut opts = cmdopts.clone();
+        let offset = u16::try_from(idx).unwrap_or(u16::MAX);
+        opts.portnum = if cmdopts.portnum > 0 { cmdopts.portnum.saturating_add(offset) } else { 0 };
+        opts.webport = if cmdopts.webport > 0 { cmdopts.webport.saturating_add(offset) } else { 0 };
+        if let Some(profile) = fileconfig.get_device_profile(arg) {
+            opts.device = PathBuf::from(&profile.path);
+            opts.name = arg.clone();
+            if profile.baudrate > 0 {
+                opts.baudrate = profile.baudrate;
+            }
+            if profile.portnum > 0 {
+                opts.portnum = profile.portnum;
+            }
+            opts.logfile = profile.log;
+        } else {
+            opts.device = PathBuf::from(arg);
+            opts.name = Path::new(arg).file_name().map_or(arg.clone(), |name| name.to_string_lossy().to_string());
+        }
+        if arg.is_empty() || !parse_path(&mut opts) {
+            return Err(format!("Could not open device {}", arg));
+        }
+        if opts.logfile.is_empty() && !cmdopts.logdir.is_empty() {
+            let mut path = PathBuf::from(config::subst_home(&cmdopts.logdir));
+            path.push(format!("{}.log", opts.name.replace(':', "_")));
+            opts.logfile = path.to_string_lossy().to_string();
+        }
+        devices.push(opts);
+    }
+    if devices.is_empty() {
+        return Err(String::from("Could not open device"));
+    }
+    Ok(devices)
+}
+
+
+// Detach from the terminal: must be done before any threads are started
+#[cfg(unix)]
+fn daemonize() -> Result<(), String> {
+    use std::os::fd::AsRawFd;
+    let null = std::fs::OpenOptions::new().read(true).write(true).open("/dev/null")
+        .map_err(|e| format!("Could not open /dev/null: {}", e))?;
+    match unsafe { libc::fork() } {
+        -1 => return Err(String::from("Could not start the daemon")),
+        0 => (),
+        _ => std::process::exit(0),
+    }
+    unsafe {
+        libc::setsid();
+        for fd in 0..3 {
+            libc::dup2(null.as_raw_fd(), fd);
+        }
+        // Do not keep the start folder busy and do not inherit the umask of the shell
+        libc::chdir(c"/".as_ptr());
+        libc::umask(0o022);
+    }
+    Ok(())
+}
+
+
+#[cfg(not(unix))]
+fn daemonize() -> Result<(), String> {
+    Err(String::from("Daemon mode is not supported on this platform"))
+}
+
+
+#[cfg(unix)]
+fn control_client(socket: &str, command: &[String]) -> i32 {
+    control_service::control_client(socket, command)
+}
+
+
+#[cfg(not(unix))]
+fn control_client(_socket: &str, _command: &[String]) -> i32 {
+    eprintln!("The control socket is not supported on this platform");
+    2
+}
+
+
 pub fn terminate(start: Instant, msg: &str) {
     trace!("Close console");
     console_service::close_console();
@@ -193,12 +420,11 @@ pub fn terminate(start: Instant, msg: &str) {
 
 
 fn main() {
-    let mut cmdopts = parse_args().run();
+    let mut cmdopts = match parse_args().run() {
+        Invocation::Terminal(cmdopts) => *cmdopts,
+        Invocation::Control(socket, command) => std::process::exit(control_client(&socket, &command)),
+    };
 
-    if !parse_path(&mut cmdopts) {
-        println!("Could not open device");
-        return;
-    }
     let fileconfig = FileConfig::new(&cmdopts.config_file, cmdopts.config_version, cmdopts.start);
 
     logger_service::init(cmdopts.tracefile.clone(), cmdopts.verbose);
@@ -224,27 +450,212 @@ fn main() {
         }
         return;
     }
-    if cmdopts.device.to_str().map_or(true, |s| s.is_empty()) {
-        // Non UTF-8 or empty string
-        println!("Could not open device");
+    let mut devices = match device_options(&cmdopts, &fileconfig) {
+        Ok(devices) => devices,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if !cmdopts.steps.is_empty() && !cmdopts.batch {
+        println!("The --send, --expect and --run steps need --batch");
+        return;
+    }
+    if cmdopts.batch && (devices.len() > 1 || cmdopts.daemon) {
+        println!("Batch mode needs a single device and cannot be used with --daemon");
         return;
     }
+    if !cmdopts.batch && !cmdopts.daemon && devices.len() > 1 && !console_service::is_terminal() {
+        println!("Pipe mode needs a single device");
+        return;
+    }
+
+    let tlsfiles = fileconfig.get_tls_files();
+    let is_server = |opts: &CmdLineConfig| (opts.portnum > 0 || opts.webport > 0) && !opts.networkdev;
+    let server = devices.iter().any(is_server);
+    let mut access = network_service::AccessList::default();
+    for opts in devices.iter_mut() {
+        if opts.portnum > 0 {
+            match network_service::listen_addresses(&opts.address, opts.portnum) {
+                Ok(listen) => opts.listen = listen,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+        if opts.webport > 0 {
+            match network_service::listen_addresses(&opts.address, opts.webport) {
+                Ok(listen) => opts.weblisten = listen,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+    }
+    if server {
+        match network_service::AccessList::new(&fileconfig.get_server_access()) {
+            Ok(list) => access = list,
+            Err(e) => {
+                println!("Server configuration: {}", e);
+                return;
+            }
+        }
+    }
+
+    let mut server_tls = None;
+    let mut client_tls = None;
+    if cmdopts.tls && devices.iter().any(|opts| opts.networkdev) {
+        match tls_service::client_config(&tlsfiles) {
+            Ok(config) => client_tls = Some(config),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+    if cmdopts.tls && server {
+        match tls_service::server_config(&tlsfiles) {
+            Ok(config) => server_tls = Some(config),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+
+    let mut logs = Vec::new();
+    for opts in devices.iter() {
+        if opts.logfile.is_empty() {
+            logs.push(None);
+            continue;
+        }
+        match term_switch::OutputLog::open(&opts.logfile) {
+            Ok(log) => logs.push(Some(log)),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    }
+
+    // Relative paths in control commands are given from the start folder
+    let folder = std::env::current_dir().unwrap_or_default();
+    if cmdopts.daemon {
+        // The daemon runs in the root folder
+        for path in [&mut cmdopts.control, &mut cmdopts.pidfile] {
+            if !path.is_empty() {
+                if let Ok(abspath) = std::path::absolute(&path) {
+                    *path = abspath.to_string_lossy().to_string();
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    let mut listener = None;
+    if !cmdopts.control.is_empty() {
+        #[cfg(unix)]
+        match control_service::bind_control(&cmdopts.control) {
+            Ok(socket) => listener = Some(socket),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            println!("The control socket is not supported on this platform");
+            return;
+        }
+    }
+
+    if cmdopts.daemon {
+        if let Err(e) = daemonize() {
+            println!("{}", e);
+            return;
+        }
+        info!("Running as daemon with process id {}", std::process::id());
+    }
+    if !cmdopts.pidfile.is_empty() {
+        if let Err(e) = std::fs::write(&cmdopts.pidfile, format!("{}\n", std::process::id())) {
+            error!("Could not write the pidfile {}: {}", cmdopts.pidfile, e);
+        }
+    }
 
-    let mut termswx = term_switch::start(cmdopts.portnum > 0);
-    let console = console_service::open_console(&mut termswx, &cmdopts, fileconfig);
-    if cmdopts.networkdev {
-        network_service::open_connection(&mut termswx, cmdopts.device, cmdopts.start);
+    let control = fileconfig.find_keyseq(TermCommand::TakeControl).unwrap_or_default();
+    let web_assets = fileconfig.get_web_assets();
+    let mut switches: Vec<TermSwitch> = Vec::new();
+    for (opts, log) in devices.iter().zip(logs) {
+        // The recent output is also used to refresh the console when switching between devices
+        // The control socket can fetch the recent output and a resize waits for a prompt in it
+        let replay = is_server(opts) || devices.len() > 1 || !cmdopts.control.is_empty() || fileconfig.get_resize_stty();
+        let history = if replay { fileconfig.get_history_size() } else { 0 };
+        let termswx = term_switch::start(is_server(opts), fileconfig.get_script_routing(), history);
+        if let Some(log) = log {
+            termswx.get_state().set_log(log);
+        }
+        switches.push(termswx);
+    }
+    // The program stops when the console quits or a quit command arrives on the control socket
+    let (quit_tx, quit_rx) = crossbeam_channel::unbounded();
+    #[cfg(unix)]
+    if let Some(listener) = listener {
+        control_service::start_control(listener, &devices, &switches, fileconfig.clone(), folder, quit_tx.clone());
+    }
+    // Batch and pipe mode end with an exit code
+    let mut batch = None;
+    if cmdopts.daemon {
+        // Nobody reads the device output in the console
+        for termswx in switches.iter() {
+            termswx.hide();
+        }
+    } else if cmdopts.batch {
+        batch = Some(batch_service::start_batch(&switches[0], &devices[0], fileconfig, quit_tx));
+    } else if !console_service::is_terminal() {
+        trace!("No terminal: using pipe mode");
+        batch = Some(batch_service::start_pipe(&switches[0], cmdopts.server, quit_tx));
     } else {
-        if cmdopts.portnum > 0 {
-            network_service::start_server(&mut termswx, cmdopts.portnum, cmdopts.maxclients, cmdopts.start);
+        let console = console_service::open_console(&switches, &devices, fileconfig);
+        std::thread::spawn(move || {
+            console.unwrap().join().unwrap();
+            let _ = quit_tx.send(());
+        });
+    }
+    for (termswx, opts) in switches.iter_mut().zip(devices.iter()) {
+        if opts.networkdev {
+            network_service::open_connection(termswx, opts.device.clone(), client_tls.clone(), opts.keeprunning,
+                                             opts.start);
+        } else {
+            let server = network_service::Server::new(termswx, opts.maxclients, access.clone(), server_tls.clone(),
+                                                      control.clone());
+            if opts.portnum > 0 {
+                network_service::start_server(&server, &opts.listen, opts.start);
+            }
+            if opts.webport > 0 {
+                web_service::start_web_server(&server, &opts.weblisten, opts.name.clone(), web_assets.clone(),
+                                              opts.start);
+            }
+            serial_service::open_device(termswx, opts.device.clone(), opts.baudrate, opts.keeprunning, opts.start);
         }
-        serial_service::open_device(&mut termswx, cmdopts.device, cmdopts.baudrate, cmdopts.keeprunning, cmdopts.start);
     }
     trace!("Waiting for Console Thread");
-    console.unwrap().join().unwrap();
+    let _ = quit_rx.recv();
     trace!("Stopping Terminal Service");
     console_service::close_console();
-    termswx.stop();
+    for termswx in switches.iter_mut() {
+        termswx.stop();
+    }
+    if !cmdopts.control.is_empty() {
+        let _ = std::fs::remove_file(&cmdopts.control);
+    }
+    if !cmdopts.pidfile.is_empty() {
+        let _ = std::fs::remove_file(&cmdopts.pidfile);
+    }
+    if let Some(batch) = batch {
+        std::process::exit(batch.join().unwrap());
+    }
     let duration = cmdopts.start.elapsed();
     println!("TermSWX completed after {}s", duration.as_secs());
 }
@@ -1,97 +1,307 @@
 //Steen Hegelund
-//Time-Stamp: 2024-Oct-18 14:04
+//Time-Stamp: 2026-Oct-18 19:39
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 //
 // Send and Receive via a TCP network connection.
 // This provides both the server side and the client side of the operation
 
 use crate::term_switch::{TermSwitch, MsgType};
+use crate::config::{ClientRole, ServerAccess};
+use crate::tls_service::TlsStream;
 
 use log::{error, trace};
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::thread;
-use std::net::{TcpListener, TcpStream, SocketAddr};
-use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream, SocketAddr, IpAddr, Shutdown};
+use std::io::{self, prelude::*};
 use crossbeam_channel::{Sender, Receiver};
 use crossterm::style::{Color, Stylize};
 use chrono;
-use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, atomic::AtomicI8, atomic::Ordering};
+use sysinfo::Networks;
+use socket2::{Domain, Protocol, Socket, Type};
+use rustls::{ClientConfig, ServerConfig};
 
 
-// Open the client connection to a server port
-pub fn open_connection(termswx: &mut TermSwitch, device: PathBuf, start: Instant) {
-    let path = device.to_str().unwrap();
-    trace!("open_connection {}", path);
+// A plain TCP connection or a TLS session on top of it
+pub enum NetStream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+}
 
-    let serial_rx = termswx.get_serial_rx();
+impl NetStream {
+    fn try_clone(&self) -> io::Result<NetStream> {
+        match self {
+            NetStream::Tcp(stream) => Ok(NetStream::Tcp(stream.try_clone()?)),
+            NetStream::Tls(stream) => Ok(NetStream::Tls(stream.try_clone()?)),
+        }
+    }
 
-    match TcpStream::connect(&path) {
-        Ok(mut stream) => {
-            let mut stream_rx = stream.try_clone().unwrap();
-            thread::spawn(move || {
-                trace!("Wait for console input");
-                let mut buffer = vec![0; 2];
-                loop {
-                    match serial_rx.recv() {
-                        Ok(MsgType::Serial(val)) => {
-                            buffer.clear();
-                            buffer.push(val);
-                            trace!("send: {:#02x} '{}'", val, val as char);
-                            match stream.write(&buffer) {
-                                Ok(_) => (),
-                                Err(_) => {
-                                    crate::terminate(start, "Client Write Error");
-                                }
-                            }
-                            let _ = stream.flush();
-                        }
-                        Ok(MsgType::Exit) => {
-                            trace!("Serial Exit received");
-                            break;
-                        }
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            NetStream::Tcp(stream) => stream,
+            NetStream::Tls(stream) => stream.socket(),
+        }
+    }
+
+    // Start a TLS session if the server has a TLS configuration
+    pub fn accept(server: &Server, socket: TcpStream) -> Result<NetStream, String> {
+        match server.tls.clone() {
+            Some(config) => Ok(NetStream::Tls(TlsStream::accept(socket, config)?)),
+            None => Ok(NetStream::Tcp(socket)),
+        }
+    }
+}
+
+
+// A client connection that can be read with a timeout during the password handshake
+pub trait ClientStream: Read + Write {
+    fn set_timeout(&self, timeout: Option<Duration>);
+}
+
+impl ClientStream for NetStream {
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        self.socket().set_read_timeout(timeout).unwrap();
+    }
+}
+
+impl Read for NetStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            NetStream::Tcp(stream) => stream.read(buf),
+            NetStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for NetStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            NetStream::Tcp(stream) => stream.write(buf),
+            NetStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            NetStream::Tcp(stream) => stream.flush(),
+            NetStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+
+// Connect to the server and start a TLS session when configured
+fn connect(path: &str, tls: Option<Arc<ClientConfig>>) -> Result<NetStream, String> {
+    let socket = TcpStream::connect(path).map_err(|e| format!("Could not connect to server: {}", e))?;
+    match tls {
+        Some(config) => {
+            let host = path.rsplit_once(':').map_or(path, |(host, _)| host).trim_matches(['[', ']']);
+            Ok(NetStream::Tls(TlsStream::connect(socket, config, host)?))
+        }
+        None => Ok(NetStream::Tcp(socket)),
+    }
+}
+
+
+// Pass characters between the server connection and the term switch until the connection is lost
+// Returns the handles of the writer and the reader thread
+// The pending messages arrived while there was no connection and are handled first
+fn run_connection(switch_tx: &Sender<MsgType>, serial_rx: &Receiver<MsgType>, pending: Vec<MsgType>,
+                  mut stream: NetStream, keeprunning: bool, start: Instant)
+                  -> (thread::JoinHandle<()>, thread::JoinHandle<()>) {
+    let mut stream_rx = stream.try_clone().unwrap();
+    let switch_tx = switch_tx.clone();
+    let serial_rx = serial_rx.clone();
+
+    let writer = thread::spawn(move || {
+        trace!("Wait for console input");
+        let mut buffer = vec![0; 2];
+        let mut pending = pending.into_iter();
+        loop {
+            let msg = match pending.next() {
+                Some(msg) => Ok(msg),
+                None => serial_rx.recv(),
+            };
+            match msg {
+                Ok(MsgType::Serial(val)) => {
+                    buffer.clear();
+                    buffer.push(val);
+                    trace!("send: {:#02x} '{}'", val, val as char);
+                    match stream.write(&buffer) {
                         Ok(_) => (),
-                        Err(e) => {
-                            error!("Error: {:?}", e);
-                            break;
+                        Err(_) if keeprunning => {
+                            // The network receiver sees the closed connection and reports it
+                            let _ = stream.socket().shutdown(Shutdown::Both);
+                        }
+                        Err(_) => {
+                            crate::terminate(start, "Client Write Error");
                         }
                     }
+                    let _ = stream.flush();
                 }
-            });
+                Ok(MsgType::SerialClose) => {
+                    trace!("Serial Close");
+                    break;
+                }
+                Ok(MsgType::Exit) => {
+                    trace!("Serial Exit received");
+                    break;
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    error!("Error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
 
-            let switch_tx = termswx.get_switch_tx();
-            thread::spawn(move || {
-                trace!("Wait for network input");
-                let mut buffer = [0; 10];
-                loop {
-                    match stream_rx.read(&mut buffer) {
-                        Ok(0) => {
-                            crate::terminate(start, "Client Connection Closed");
-                        }
-                        Ok(cnt) => {
-                            for idx in 0..cnt {
-                                trace!("received: {:#02x} '{}'", buffer[idx], buffer[idx] as char);
-                                let msg = MsgType::Serial(buffer[idx]);
-                                switch_tx.send(msg).unwrap();
-                            }
-                        }
-                        Err(e) => {
-                            println!("Client Recv Error: {:?}", e);
-                            crate::terminate(start, "Connect has been reset");
-                        }
+    let reader = thread::spawn(move || {
+        trace!("Wait for network input");
+        let mut buffer = [0; 10];
+        loop {
+            match stream_rx.read(&mut buffer) {
+                Ok(0) if keeprunning => {
+                    trace!("Client Connection Closed");
+                    switch_tx.send(MsgType::SerialClose).unwrap();
+                    break;
+                }
+                Ok(0) => {
+                    crate::terminate(start, "Client Connection Closed");
+                }
+                Ok(cnt) => {
+                    for idx in 0..cnt {
+                        trace!("received: {:#02x} '{}'", buffer[idx], buffer[idx] as char);
+                        let msg = MsgType::Serial(buffer[idx]);
+                        switch_tx.send(msg).unwrap();
                     }
                 }
-            });
+                Err(e) if keeprunning => {
+                    trace!("Client Recv Error: {:?}", e);
+                    switch_tx.send(MsgType::SerialClose).unwrap();
+                    break;
+                }
+                Err(e) => {
+                    println!("Client Recv Error: {:?}", e);
+                    crate::terminate(start, "Connect has been reset");
+                }
+            }
         }
-        Err(e) => {
-            error!("Error: {:?}", e);
-            crate::terminate(start, "Could not connect to server");
+    });
+    (writer, reader)
+}
+
+
+fn show_server_disconnect(path: &str, reason: &str) {
+    let msg = format!("Disconnected from: {:?}: {}", path, reason);
+    trace!("{}", msg);
+    println!("\r{}\r", msg.with(Color::White).on(Color::DarkRed));
+}
+
+fn show_server_connect(path: &str) {
+    let msg = format!("Connected to: {:?}", path);
+    trace!("{}", msg);
+    println!("\r{}\r", msg.with(Color::White).on(Color::DarkBlue));
+}
+
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+// A connection that is closed sooner is retried with a growing delay like a failed connect
+const RECONNECT_STABLE: Duration = Duration::from_secs(10);
+
+
+// Open the client connection to a server port and reconnect with a growing delay if it should keep running
+// Drop what was typed while there was no connection and keep the other messages
+fn drain_typed(serial_rx: &Receiver<MsgType>) -> Vec<MsgType> {
+    serial_rx.try_iter().filter(|msg| !matches!(msg, MsgType::Serial(_))).collect()
+}
+
+
+pub fn open_connection(termswx: &mut TermSwitch, device: PathBuf, tls: Option<Arc<ClientConfig>>, keeprunning: bool,
+                       start: Instant) {
+    let switch_tx = termswx.get_switch_tx();
+    let serial_rx = termswx.get_serial_rx();
+    let state = termswx.get_state();
+
+    thread::spawn(move || {
+        let path = device.to_str().unwrap();
+        trace!("open_connection {}", path);
+        let mut lost = false;
+        let mut delay = RECONNECT_DELAY;
+        loop {
+            match connect(path, tls.clone()) {
+                Ok(stream) => {
+                    if lost {
+                        show_server_connect(path);
+                    }
+                    let pending = drain_typed(&serial_rx);
+                    let connected = Instant::now();
+                    state.connected.store(true, Ordering::Relaxed);
+                    let (writer, reader) = run_connection(&switch_tx, &serial_rx, pending, stream, keeprunning,
+                                                          start);
+                    reader.join().unwrap();
+                    // The writer stops on the close message sent by the reader and must be gone before the
+                    // next connection gets its own writer
+                    writer.join().unwrap();
+                    state.connected.store(false, Ordering::Relaxed);
+                    show_server_disconnect(path, "Connection closed");
+                    lost = true;
+                    // A server that closes the connection at once (too many clients, wrong password) is not
+                    // connected again in a busy loop
+                    if connected.elapsed() >= RECONNECT_STABLE {
+                        delay = RECONNECT_DELAY;
+                    }
+                    trace!("Reconnect to {} in {}s", path, delay.as_secs());
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                }
+                Err(e) if keeprunning => {
+                    error!("Error: {}", e);
+                    if !lost {
+                        show_server_disconnect(path, &e);
+                        lost = true;
+                    }
+                    trace!("Reconnect to {} in {}s", path, delay.as_secs());
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                }
+                Err(e) => {
+                    error!("Error: {}", e);
+                    crate::terminate(start, &e);
+                }
+            }
         }
+    });
+}
+
+
+// Find the take control key sequence in the client input
+fn find_keyseq(input: &[u8], keyseq: &[u8]) -> Option<usize> {
+    if keyseq.is_empty() {
+        return None;
+    }
+    input.windows(keyseq.len()).position(|window| window == keyseq)
+}
+
+
+// Turn client input into messages for the term switch
+pub fn client_input(addr: SocketAddr, mut input: &[u8], control: &[u8]) -> Vec<MsgType> {
+    let mut msgs = Vec::new();
+    while let Some(pos) = find_keyseq(input, control) {
+        msgs.extend(input[..pos].iter().map(|val| MsgType::NetInput(addr, *val)));
+        msgs.push(MsgType::TakeControl(Some(addr)));
+        input = &input[pos + control.len()..];
     }
+    msgs.extend(input.iter().map(|val| MsgType::NetInput(addr, *val)));
+    msgs
 }
 
 
-fn serve_client(addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream_rx: TcpStream, switch_tx: &Sender<MsgType>) {
+fn serve_client(addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream_rx: NetStream, switch_tx: &Sender<MsgType>,
+                control: Vec<u8>) {
     let mut stream_tx = stream_rx.try_clone().unwrap();
     let sw_tx = switch_tx.clone();
 
@@ -108,6 +318,8 @@ fn serve_client(addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream_rx: T
                 }
                 Ok(MsgType::Exit) => {
                     trace!("Network Exit received");
+                    // Also stops the network receiver when the client is disconnected by the server
+                    let _ = stream_tx.socket().shutdown(Shutdown::Both);
                     break;
                 }
                 Ok(_) => (),
@@ -132,9 +344,8 @@ fn serve_client(addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream_rx: T
                     break;
                 }
                 Ok(cnt) => {
-                    for idx in 0..cnt {
-                        trace!("input: {:#02x} {} from {}", buffer[idx], buffer[idx] as char, addr);
-                        let msg = MsgType::Console(buffer[idx]);
+                    for msg in client_input(addr, &buffer[..cnt], &control) {
+                        trace!("input: {:?} from {}", msg, addr);
                         match sw_tx.send(msg) {
                             Ok(_) => (),
                             Err(_) => {
@@ -162,66 +373,576 @@ fn serve_client(addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream_rx: T
 }
 
 
-// Start a TCP server on a port number
-pub fn start_server(termswx: &mut TermSwitch, portnum: u16, maxclients: i8, start: Instant) {
-    let path = format!("0.0.0.0:{}", portnum);
-    trace!("start_server path: {}", path);
+// IPv6 link local addresses cannot be used without a scope
+fn is_link_local(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80)
+}
 
-    let switch_tx = termswx.get_switch_tx();
-    let network_rx = termswx.get_network_rx();
-    let clients = termswx.get_clients();
 
-    thread::spawn(move || {
-        match TcpListener::bind(&path) {
-            Ok(listener) => {
-                for stream in listener.incoming() {
-                    match stream {
-                        Ok(stream_rx) => {
-                            let addr = stream_rx.peer_addr().unwrap();
-                            let now = chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-
-                            if clients.load(Ordering::Relaxed) >= maxclients {
-                                let text = format!("Client rejected at {} from {}: Maximum clients connected: {}",
-                                    now, addr, maxclients);
-                                trace!("{}", text);
-                                println!("\r{}\r", text.with(Color::White).on(Color::Black));
-                                continue;
-                            }
+// Get the addresses to listen on from IP addresses or interface names: all IPv4 addresses by default
+pub fn listen_addresses(names: &[String], portnum: u16) -> Result<Vec<SocketAddr>, String> {
+    if names.is_empty() {
+        return Ok(vec![SocketAddr::from(([0, 0, 0, 0], portnum))]);
+    }
+    let mut addrs = Vec::new();
+    for name in names {
+        if let Ok(ip) = name.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            addrs.push(SocketAddr::new(ip, portnum));
+            continue;
+        }
+        let networks = Networks::new_with_refreshed_list();
+        let Some((_, network)) = networks.iter().find(|(iface, _)| *iface == name) else {
+            return Err(format!("Unknown address or interface: {}", name));
+        };
+        let ips: Vec<IpAddr> = network.ip_networks().iter()
+            .map(|ipnet| ipnet.addr)
+            .filter(|ip| !is_link_local(ip))
+            .collect();
+        if ips.is_empty() {
+            return Err(format!("Interface {} has no addresses", name));
+        }
+        addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, portnum)));
+    }
+    addrs.sort();
+    addrs.dedup();
+    Ok(addrs)
+}
 
-                            let text = format!("\r\nClient connection established at {} from {}", now, addr);
-                            trace!("{}", text);
-                            println!("\r{}\r", text.with(Color::White).on(Color::Black));
 
-                            // The thread must exit to close the network_rx channel
-                            // or there will be multiple receivers fighting for the same events
+// A single address or a network in CIDR notation: 192.168.1.0/24 or fd00::/8
+#[derive(Debug, Clone, PartialEq)]
+struct AddressRange {
+    addr: IpAddr,
+    prefix: u32,
+}
 
-                            // Get a client connection channel
-                            match switch_tx.send(MsgType::Add(addr)) {
-                                Ok(_) => (),
-                                Err(_) => {
-                                    error!("Could not send client address to term_switch");
-                                }
-                            }
-                            match network_rx.recv() {
-                                Ok(MsgType::Added(client_rx)) => {
-                                    serve_client(addr, client_rx, stream_rx, &switch_tx);
-                                }
-                                Ok(_) => (),
-                                Err(_) => (),
-                            }
-                        }
-                        Err(e) => {
-                            println!("Client connection error {:?}!\r\n", e);
-                            error!("Error: {:?}", e);
-                        }
-                    }
+impl AddressRange {
+    fn parse(text: &str) -> Result<AddressRange, String> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| format!("Invalid address: {}", text))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u32>().ok().filter(|&p| p <= bits)
+                .ok_or(format!("Invalid prefix length: {}", text))?,
+            None => bits,
+        };
+        Ok(AddressRange { addr, prefix })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        let shift = bits - self.prefix;
+        shift == bits || net >> shift == ip >> shift
+    }
+}
+
+
+// Clients are checked against the deny list first, then the allow list if it has entries
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    password: String,
+    allow: Vec<AddressRange>,
+    deny: Vec<AddressRange>,
+    role: ClientRole,
+    writers: Vec<AddressRange>,
+    observers: Vec<AddressRange>,
+}
+
+impl AccessList {
+    pub fn new(access: &ServerAccess) -> Result<AccessList, String> {
+        let parse = |list: &Vec<String>| list.iter().map(|text| AddressRange::parse(text))
+            .collect::<Result<Vec<AddressRange>, String>>();
+        Ok(AccessList {
+            password: access.password.clone(),
+            allow: parse(&access.allow)?,
+            deny: parse(&access.deny)?,
+            role: access.role,
+            writers: parse(&access.writers)?,
+            observers: parse(&access.observers)?,
+        })
+    }
+
+    // The address lists take precedence over the default role
+    fn role(&self, ip: &IpAddr) -> ClientRole {
+        if self.writers.iter().any(|range| range.contains(ip)) {
+            ClientRole::Writer
+        } else if self.observers.iter().any(|range| range.contains(ip)) {
+            ClientRole::Observer
+        } else {
+            self.role
+        }
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    pub fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
+
+
+const PASSWORD_ATTEMPTS: usize = 3;
+// The time allowed for all the password attempts
+const PASSWORD_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+// Read a line without echo, skipping the LF of a CRLF pair from the previous line
+// A client that sends a byte now and then cannot keep the prompt open past the deadline
+fn read_password(stream: &mut impl ClientStream, deadline: Instant) -> Option<String> {
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return None;
+        }
+        stream.set_timeout(Some(remaining));
+        match stream.read(&mut byte) {
+            Ok(1) => match byte[0] {
+                b'\r' | b'\n' if line.is_empty() => (),
+                b'\r' | b'\n' => return Some(String::from_utf8_lossy(&line).to_string()),
+                val => line.push(val),
+            },
+            _ => return None,
+        }
+        if line.len() > 256 {
+            return None;
+        }
+    }
+}
+
+
+// Compare all bytes whatever the first difference so the time taken does not tell how much of a guess matched
+fn same_password(answer: &str, password: &str) -> bool {
+    let (answer, password) = (answer.as_bytes(), password.as_bytes());
+    let mut diff = answer.len() ^ password.len();
+    for idx in 0..answer.len().max(password.len()) {
+        diff |= (answer.get(idx).unwrap_or(&0) ^ password.get(idx).unwrap_or(&0)) as usize;
+    }
+    diff == 0
+}
+
+
+// Ask for the password and give the client a few attempts before closing the connection.
+// Returns the first input byte if it arrived right after the password.
+pub fn authenticate(stream: &mut impl ClientStream, addr: SocketAddr, password: &str) -> Result<Option<u8>, String> {
+    if password.is_empty() {
+        return Ok(None);
+    }
+    let deadline = Instant::now() + PASSWORD_TIMEOUT;
+    for _ in 0..PASSWORD_ATTEMPTS {
+        let _ = stream.write_all(b"TermSWX password: ");
+        let _ = stream.flush();
+        let Some(answer) = read_password(stream, deadline) else {
+            return Err(String::from("No password received"));
+        };
+        let _ = stream.write_all(b"\r\n");
+        if same_password(&answer, password) {
+            // Drop the LF following the CR of the accepted password
+            stream.set_timeout(Some(Duration::from_millis(100)));
+            let mut byte = [0; 1];
+            let pending = match stream.read(&mut byte) {
+                Ok(1) if byte[0] != b'\n' => Some(byte[0]),
+                _ => None,
+            };
+            stream.set_timeout(None);
+            return Ok(pending);
+        }
+        let _ = stream.write_all(b"Wrong password\r\n");
+        let now = chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let text = format!("Failed password attempt at {} from {}", now, addr);
+        error!("{}", text);
+        println!("\r{}\r", text.with(Color::White).on(Color::Black));
+    }
+    Err(String::from("Too many failed password attempts"))
+}
+
+
+// Shared state of the listeners of the TCP server and the web terminal
+#[derive(Clone)]
+pub struct Server {
+    pub switch_tx: Sender<MsgType>,
+    network_rx: Receiver<MsgType>,
+    clients: Arc<AtomicI8>,
+    maxclients: i8,
+    // Connections in the TLS, password or HTTP handshake
+    pending: Arc<AtomicI8>,
+    register: Arc<Mutex<()>>,
+    pub access: Arc<AccessList>,
+    tls: Option<Arc<ServerConfig>>,
+    pub control: Vec<u8>,
+}
+
+impl Server {
+    pub fn new(termswx: &mut TermSwitch, maxclients: i8, access: AccessList, tls: Option<Arc<ServerConfig>>,
+               control: Vec<u8>) -> Server {
+        Server {
+            switch_tx: termswx.get_switch_tx(),
+            network_rx: termswx.get_network_rx(),
+            clients: termswx.get_clients(),
+            maxclients,
+            pending: Arc::new(AtomicI8::new(0)),
+            register: Arc::new(Mutex::new(())),
+            access: Arc::new(access),
+            tls,
+            control,
+        }
+    }
+}
+
+
+// A connection in its handshake counts against the maxclients limit until it is dropped
+pub struct Handshake {
+    pending: Arc<AtomicI8>,
+}
+
+impl Drop for Handshake {
+    fn drop(&mut self) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+// Reject the connection before a thread is started for its handshake when the clients would exceed the limit
+pub fn begin_handshake(server: &Server, addr: SocketAddr) -> Option<Handshake> {
+    let _guard = server.register.lock().unwrap();
+    if server.clients.load(Ordering::Relaxed) + server.pending.load(Ordering::Relaxed) >= server.maxclients {
+        reject_client(addr, &format!("Maximum clients connected: {}", server.maxclients));
+        return None;
+    }
+    server.pending.fetch_add(1, Ordering::Relaxed);
+    Some(Handshake { pending: server.pending.clone() })
+}
+
+
+pub fn reject_client(addr: SocketAddr, reason: &str) {
+    let now = chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let text = format!("Client rejected at {} from {}: {}", now, addr, reason);
+    error!("{}", text);
+    println!("\r{}\r", text.with(Color::White).on(Color::Black));
+}
+
+
+// Register a client with the term switch and get its connection channel
+pub fn register_client(server: &Server, addr: SocketAddr) -> Option<Receiver<MsgType>> {
+    // Only one listener at a time can add a client
+    let _guard = server.register.lock().unwrap();
+    if server.clients.load(Ordering::Relaxed) >= server.maxclients {
+        reject_client(addr, &format!("Maximum clients connected: {}", server.maxclients));
+        return None;
+    }
+
+    let role = server.access.role(&addr.ip());
+    let now = chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let text = format!("\r\nClient connection established at {} from {} as {:?}", now, addr, role);
+    trace!("{}", text);
+    println!("\r{}\r", text.with(Color::White).on(Color::Black));
+
+    // Get a client connection channel
+    match server.switch_tx.send(MsgType::Add(addr, role)) {
+        Ok(_) => (),
+        Err(_) => {
+            error!("Could not send client address to term_switch");
+        }
+    }
+    match server.network_rx.recv() {
+        Ok(MsgType::Added(client_rx)) => Some(client_rx),
+        Ok(_) => None,
+        Err(_) => None,
+    }
+}
+
+
+// Authenticate the client and hand it over to the term switch
+fn add_client(server: Server, addr: SocketAddr, socket: TcpStream, handshake: Handshake) {
+    let mut stream_rx = match NetStream::accept(&server, socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            reject_client(addr, &e);
+            return;
+        }
+    };
+    let pending = match authenticate(&mut stream_rx, addr, &server.access.password) {
+        Ok(pending) => pending,
+        Err(e) => {
+            reject_client(addr, &e);
+            return;
+        }
+    };
+    let client_rx = register_client(&server, addr);
+    drop(handshake);
+    if let Some(client_rx) = client_rx {
+        if let Some(val) = pending {
+            let _ = server.switch_tx.send(MsgType::NetInput(addr, val));
+        }
+        serve_client(addr, client_rx, stream_rx, &server.switch_tx, server.control.clone());
+    }
+}
+
+
+// Accept clients on one of the server addresses
+fn listen(listener: TcpListener, server: Server) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream_rx) => {
+                let addr = stream_rx.peer_addr().unwrap();
+                if !server.access.permits(&addr.ip()) {
+                    reject_client(addr, "Address not allowed");
+                    continue;
                 }
+                let Some(handshake) = begin_handshake(&server, addr) else {
+                    continue;
+                };
+                // The TLS and password handshakes must not block other clients
+                let server = server.clone();
+                thread::spawn(move || add_client(server, addr, stream_rx, handshake));
             }
             Err(e) => {
+                println!("Client connection error {:?}!\r\n", e);
                 error!("Error: {:?}", e);
-                crate::terminate(start, "Port number already in use");
             }
         }
-    });
+    }
 }
 
+
+// An IPv6 listener only takes IPv6 connections so it can be used next to an IPv4 listener on the same port
+pub fn bind_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+
+// Start a TCP server with a listener on each address
+pub fn start_server(server: &Server, addrs: &[SocketAddr], start: Instant) {
+    for addr in addrs.iter().copied() {
+        trace!("start_server address: {}", addr);
+        let server = server.clone();
+
+        thread::spawn(move || {
+            match bind_listener(addr) {
+                Ok(listener) => listen(listener, server),
+                Err(e) => {
+                    error!("Error: {:?}", e);
+                    crate::terminate(start, &format!("Could not listen on {}: {}", addr, e));
+                }
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(listen_addresses(&[], 5555), Ok(vec!["0.0.0.0:5555".parse().unwrap()]));
+        let names = vec![String::from("::"), String::from("127.0.0.1"), String::from("[::1]"), String::from("::1")];
+        let expected: Vec<SocketAddr> = vec!["127.0.0.1:5555".parse().unwrap(), "[::]:5555".parse().unwrap(),
+                                             "[::1]:5555".parse().unwrap()];
+        assert_eq!(listen_addresses(&names, 5555), Ok(expected));
+        assert!(listen_addresses(&[String::from("nosuchif0")], 5555).is_err());
+    }
+
+    #[test]
+    fn dual_stack() {
+        let ipv4 = bind_listener("0.0.0.0:0".parse().unwrap()).unwrap();
+        let portnum = ipv4.local_addr().unwrap().port();
+        let ipv6 = bind_listener(SocketAddr::from(([0u16; 8], portnum))).unwrap();
+        assert_eq!(ipv6.local_addr().unwrap().port(), portnum);
+    }
+
+    #[test]
+    fn link_local() {
+        assert!(is_link_local(&"fe80::1".parse().unwrap()));
+        assert!(!is_link_local(&"2001:db8::1".parse().unwrap()));
+        assert!(!is_link_local(&"169.254.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn address_range() {
+        let range = AddressRange::parse("192.168.1.0/24").unwrap();
+        assert!(range.contains(&"192.168.1.77".parse().unwrap()));
+        assert!(range.contains(&"::ffff:192.168.1.77".parse().unwrap()));
+        assert!(!range.contains(&"192.168.2.1".parse().unwrap()));
+        assert!(!range.contains(&"fd00::1".parse().unwrap()));
+        let range = AddressRange::parse("fd00::/8").unwrap();
+        assert!(range.contains(&"fd12:3456::1".parse().unwrap()));
+        assert!(!range.contains(&"fe80::1".parse().unwrap()));
+        assert!(AddressRange::parse("0.0.0.0/0").unwrap().contains(&"10.1.2.3".parse().unwrap()));
+        assert_eq!(AddressRange::parse("10.0.0.1").unwrap().prefix, 32);
+        assert!(AddressRange::parse("10.0.0.0/33").is_err());
+        assert!(AddressRange::parse("nohost/8").is_err());
+    }
+
+    #[test]
+    fn access_list() {
+        let access = ServerAccess {
+            allow: vec![String::from("10.0.0.0/8"), String::from("::1")],
+            deny: vec![String::from("10.0.0.13")],
+            ..ServerAccess::default()
+        };
+        let list = AccessList::new(&access).unwrap();
+        assert!(list.permits(&"10.1.2.3".parse().unwrap()));
+        assert!(list.permits(&"::1".parse().unwrap()));
+        assert!(!list.permits(&"10.0.0.13".parse().unwrap()));
+        assert!(!list.permits(&"192.168.1.1".parse().unwrap()));
+        assert!(AccessList::default().permits(&"192.168.1.1".parse().unwrap()));
+        let access = ServerAccess { deny: vec![String::from("10.0.0.0/40")], ..access };
+        assert!(AccessList::new(&access).is_err());
+    }
+
+    #[test]
+    fn client_roles() {
+        let access = ServerAccess {
+            role: ClientRole::Observer,
+            writers: vec![String::from("192.168.1.10"), String::from("::1")],
+            observers: vec![String::from("192.168.1.0/24")],
+            ..ServerAccess::default()
+        };
+        let list = AccessList::new(&access).unwrap();
+        assert_eq!(list.role(&"192.168.1.10".parse().unwrap()), ClientRole::Writer);
+        assert_eq!(list.role(&"::1".parse().unwrap()), ClientRole::Writer);
+        assert_eq!(list.role(&"192.168.1.11".parse().unwrap()), ClientRole::Observer);
+        assert_eq!(list.role(&"10.0.0.1".parse().unwrap()), ClientRole::Observer);
+        assert_eq!(AccessList::default().role(&"10.0.0.1".parse().unwrap()), ClientRole::Writer);
+    }
+
+    #[test]
+    fn input_messages() {
+        let addr: SocketAddr = "127.0.0.1:5555".parse().unwrap();
+        let msgs = client_input(addr, b"a\x07b", b"\x07");
+        assert!(matches!(msgs.as_slice(), [MsgType::NetInput(_, b'a'), MsgType::TakeControl(Some(_)),
+                                           MsgType::NetInput(_, b'b')]));
+        assert_eq!(client_input(addr, b"ab", b"").len(), 2);
+    }
+
+    #[test]
+    fn passwords() {
+        assert!(same_password("secret", "secret"));
+        assert!(!same_password("secreT", "secret"));
+        assert!(!same_password("secret1", "secret"));
+        assert!(!same_password("", "secret"));
+    }
+
+    // Sends a byte of the password now and then without ever ending the line
+    struct SlowClient;
+
+    impl Read for SlowClient {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(20));
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    impl Write for SlowClient {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ClientStream for SlowClient {
+        fn set_timeout(&self, _timeout: Option<Duration>) {}
+    }
+
+    #[test]
+    fn password_deadline() {
+        let start = Instant::now();
+        assert_eq!(read_password(&mut SlowClient, start + Duration::from_millis(200)), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pending_handshakes() {
+        let routing = crate::config::ScriptRouting {
+            output: crate::config::ScriptRoute::All,
+            write: crate::config::ScriptRoute::All,
+        };
+        let mut termswx = crate::term_switch::start(true, routing, 0);
+        let server = Server::new(&mut termswx, 2, AccessList::default(), None, Vec::new());
+        let addr: SocketAddr = "127.0.0.1:5555".parse().unwrap();
+        let first = begin_handshake(&server, addr);
+        let second = begin_handshake(&server, addr);
+        assert!(first.is_some() && second.is_some());
+        assert!(begin_handshake(&server, addr).is_none());
+        drop(first);
+        assert!(begin_handshake(&server, addr).is_some());
+    }
+
+    #[test]
+    fn reconnect() {
+        let routing = crate::config::ScriptRouting {
+            output: crate::config::ScriptRoute::All,
+            write: crate::config::ScriptRoute::All,
+        };
+        let mut termswx = crate::term_switch::start(false, routing, 0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let device = PathBuf::from(listener.local_addr().unwrap().to_string());
+        open_connection(&mut termswx, device, None, true, Instant::now());
+        let switch_tx = termswx.get_switch_tx();
+        // Each closed connection is followed by a new one after a growing delay
+        let mut closed: Option<Instant> = None;
+        for count in 0..3 {
+            let (mut stream, _) = listener.accept().unwrap();
+            if let Some(closed) = closed {
+                assert!(closed.elapsed() >= RECONNECT_DELAY * count);
+            }
+            thread::sleep(Duration::from_millis(50));
+            for val in b"uname -a\r" {
+                switch_tx.send(MsgType::Console(*val)).unwrap();
+            }
+            // All the input goes to the new connection
+            stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let mut received = vec![0; 9];
+            stream.read_exact(&mut received).unwrap();
+            assert_eq!(received, b"uname -a\r");
+            drop(stream);
+            closed = Some(Instant::now());
+        }
+    }
+
+    #[test]
+    fn typed_while_disconnected() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for msg in [MsgType::Serial(b'l'), MsgType::SerialBaudrate(9600), MsgType::Serial(b's'), MsgType::Exit] {
+            tx.send(msg).unwrap();
+        }
+        let kept = drain_typed(&rx);
+        assert!(matches!(kept[..], [MsgType::SerialBaudrate(9600), MsgType::Exit]));
+        assert!(rx.is_empty());
+    }
+
+    #[test]
+    fn control_keyseq() {
+        assert_eq!(find_keyseq(b"ls\x07", b"\x07"), Some(2));
+        assert_eq!(find_keyseq(b"ab\x1b[15~cd", b"\x1b[15~"), Some(2));
+        assert_eq!(find_keyseq(b"ls -l", b"\x07"), None);
+        assert_eq!(find_keyseq(b"ls -l", b""), None);
+    }
+}
@@ -1,27 +1,29 @@
 //Steen Hegelund
-//Time-Stamp: 2024-Oct-17 20:27
+//Time-Stamp: 2026-Oct-18 19:36
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 //
 // Run python scripts
 
-use crate::term_switch::MsgType;
+use crate::term_switch::{MsgType, register_script};
 use crate::ansi_filter::AnsiFilter;
-use crate::config::subst_home;
+use crate::config::{subst_home, ScriptTimeouts};
 use crate::console_service::show_error;
 
 use log::{error, info, trace};
 use std::io::{self, BufRead, BufReader, Write, Read};
 use std::thread;
 use std::thread::sleep;
-use std::sync::Arc;
-use std::process::{self, Child, ChildStdin};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::process::{self, Child, ChildStdin, ExitStatus};
+use std::time::{Duration, Instant};
 use crossterm::terminal;
 use crossterm::style::{Color, Stylize};
-use crossbeam_channel::{Sender, Receiver, unbounded};
+use crossbeam_channel::{Sender, Receiver, unbounded, bounded};
 use std::sync::{atomic::AtomicU32, atomic::AtomicBool, atomic::Ordering};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use sysinfo::{Pid, Signal, System};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use sysinfo::{ProcessStatus, ProcessesToUpdate};
 
 const SCRIPT_ALERT: char = '\u{11}';
 const SCRIPT_MENU_TITLE: char = '\u{12}';
@@ -31,28 +33,154 @@ const SCRIPT_USER_TEXT: char = '\u{15}';
 const SCRIPT_BINARY_ON: char = '\u{16}';
 const SCRIPT_BINARY_OFF: char = '\u{17}';
 
+// Number of unprefixed stderr lines (e.g. a python traceback) kept for the exit summary
+const STDERR_TAIL_LINES: usize = 10;
+
+// Number of finished scripts kept for the help menu
+const SCRIPT_RESULTS: usize = 10;
+
+// Time allowed for a script to exit after the terminate signal before it is killed
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const KILL_DELAY: Duration = Duration::from_secs(3);
+
 pub struct ScriptCommand {
     pub arg: String,
     pub tx: Sender<MsgType>,
-    pub rx: Receiver<MsgType>,
-    pub pid: Arc::<AtomicU32>,
+    pub scripts: ScriptTable,
+    pub results: ScriptResults,
     pub python: String,
     pub envir: HashMap<String, String>,
-    pub in_prompt: Arc::<AtomicBool>,
-    pub binary_mode: Arc::<AtomicBool>,
+    pub in_prompt: Arc::<AtomicU32>,
+    pub timeouts: ScriptTimeouts,
+}
+
+
+// A running script as listed in the help menu
+// A file transfer has no process id but is stopped using its cancel flag
+#[derive(Debug, Clone)]
+pub struct ScriptInfo {
+    pub id: u32,
+    pub pid: u32,
+    pub arg: String,
+    pub start: Instant,
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl ScriptInfo {
+    pub fn stop(&self) {
+        match &self.cancel {
+            Some(cancel) => cancel.store(true, Ordering::Relaxed),
+            None => signal(self.pid),
+        }
+    }
+}
+
+// The running scripts in the order they were started
+pub type ScriptTable = Arc<Mutex<Vec<ScriptInfo>>>;
+
+
+// The outcome of a finished script
+#[derive(Debug, Clone)]
+pub struct ScriptResult {
+    pub id: u32,
+    pub arg: String,
+    pub code: Option<i32>,
+    pub status: String,
+    pub success: bool,
+    pub stderr: Vec<String>,
+    pub end: Instant,
 }
 
+// The most recently finished scripts, the latest last
+pub type ScriptResults = Arc<Mutex<VecDeque<ScriptResult>>>;
+
 
 // Terminate a running/dead script using the process id
 pub fn signal(u32pid: u32) {
-    let pid = usize::try_from(u32pid).unwrap();
-    info!("Kill script process id: {pid}");
+    let pid = Pid::from(usize::try_from(u32pid).unwrap());
+    info!("Terminate script process id: {pid}");
     let s = System::new_all();
-    if let Some(process) = s.process(Pid::from(pid)) {
+    if let Some(process) = s.process(pid) {
         #[cfg(target_os = "windows")]
         process.kill_with(Signal::Kill);
         #[cfg(any(target_os = "linux", target_os = "macos"))]
-        process.kill_with(Signal::Term);
+        {
+            process.kill_with(Signal::Term);
+            thread::spawn(move || escalate(pid));
+        }
+    }
+}
+
+
+// Kill the script if it is still running after the terminate signal
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn escalate(pid: Pid) {
+    let mut s = System::new();
+    let start = Instant::now();
+    while start.elapsed() < KILL_DELAY {
+        thread::sleep(Duration::from_millis(100));
+        s.refresh_processes(ProcessesToUpdate::Some(&[pid]), true);
+        match s.process(pid) {
+            Some(process) if process.status() != ProcessStatus::Zombie => (),
+            _ => return,
+        }
+    }
+    info!("Kill script process id: {pid}");
+    let text = format!("Script with process id {} did not terminate: killing it", pid);
+    println!("\r{}\r", text.with(Color::White).on(Color::DarkRed));
+    if let Some(process) = s.process(pid) {
+        process.kill_with(Signal::Kill);
+    }
+}
+
+
+// Describe how a script process ended
+fn exit_text(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exit code {}", code);
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signum) = status.signal() {
+            return format!("terminated by signal {}", signum);
+        }
+    }
+    String::from("terminated")
+}
+
+
+// The reason to stop a script that has been running or has been idle for the given time
+fn expired(timeouts: &ScriptTimeouts, running: Duration, idle: Duration) -> Option<String> {
+    if timeouts.timeout.is_some_and(|limit| running >= limit) {
+        Some(format!("wall-clock timeout of {}s", timeouts.timeout.unwrap().as_secs()))
+    } else if timeouts.idle.is_some_and(|limit| idle >= limit) {
+        Some(format!("no output for {}s", timeouts.idle.unwrap().as_secs()))
+    } else {
+        None
+    }
+}
+
+
+// Stop the script when it has been running for too long or has not produced any output for a while
+// A script waiting for the user to answer its prompt is not idle
+fn watchdog(id: u32, pid: u32, timeouts: ScriptTimeouts, activity: Arc<Mutex<Instant>>, in_prompt: Arc<AtomicU32>,
+            done: Arc<AtomicBool>, reason: Arc<Mutex<Option<String>>>) {
+    let start = Instant::now();
+    while !done.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(200));
+        if in_prompt.load(Ordering::Relaxed) == id {
+            *activity.lock().unwrap() = Instant::now();
+        }
+        let idle = activity.lock().unwrap().elapsed();
+        if let Some(text) = expired(&timeouts, start.elapsed(), idle) {
+            let msg = format!("Script {} with process id {} stopped by the watchdog: {}", id, pid, text);
+            info!("{}", msg);
+            println!("\r{}\r", msg.with(Color::White).on(Color::DarkRed));
+            *reason.lock().unwrap() = Some(text);
+            signal(pid);
+            break;
+        }
     }
 }
 
@@ -90,21 +218,50 @@ fn script_stdin_write(stdin: &mut ChildStdin, ch: u8, sleep: bool) -> bool {
 
 
 // Run a child process
-fn child_process(cmd: ScriptCommand, mut child: Child) {
-    cmd.pid.store(child.id(), Ordering::Relaxed);
+fn child_process(cmd: ScriptCommand, id: u32, script_rx: Receiver<MsgType>, mut child: Child) {
+    cmd.scripts.lock().unwrap().push(ScriptInfo {
+        id,
+        pid: child.id(),
+        arg: cmd.arg.clone(),
+        start: Instant::now(),
+        cancel: None,
+    });
     let mut stdin = child.stdin.take().expect("Get stdin");
     let mut stdout = child.stdout.take().expect("Get stdout");
     let stderr = child.stderr.take().expect("Get stderr");
     let (echo_tx, echo_rx) = unbounded();
     let mut filter = AnsiFilter::new();
-    let binary_mode = cmd.binary_mode.clone();
-    let endtext = format!("End {} with process id {}\r", cmd.arg, child.id());
+    // Binary mode of this script: the term switch is told so it can route the device output
+    let script_binary = Arc::new(AtomicBool::new(false));
+    // Time of the latest script output and the reason if the watchdog stopped the script
+    let activity = Arc::new(Mutex::new(Instant::now()));
+    let reason: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let done = Arc::new(AtomicBool::new(false));
+    let endtext = format!("End {} with process id {}", cmd.arg, child.id());
+
+    if cmd.timeouts.timeout.is_some() || cmd.timeouts.idle.is_some() {
+        let pid = child.id();
+        let timeouts = cmd.timeouts;
+        let activity = activity.clone();
+        let done = done.clone();
+        let reason = reason.clone();
+        let in_prompt = cmd.in_prompt.clone();
+        thread::spawn(move || watchdog(id, pid, timeouts, activity, in_prompt, done, reason));
+    }
+
+    // The stderr thread hands over the last unprefixed lines when the script ends
+    let (tail_tx, tail_rx) = bounded(1);
 
     // Get serial output and send to the script stdin
+    let scripts = cmd.scripts.clone();
+    let results = cmd.results.clone();
+    let arg = cmd.arg.clone();
+    let in_prompt = cmd.in_prompt.clone();
+    let binary_mode = script_binary.clone();
     thread::spawn(move || {
         const CR: u8 = 0xd;
         loop {
-            match cmd.rx.recv() {
+            match script_rx.recv() {
                 Ok(MsgType::Console(ch)) => {
                     if binary_mode.load(Ordering::Relaxed) {
                         trace!("Script_rx (binary): {:#02x} '{}'", ch, ch as char);
@@ -132,16 +289,14 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                         }
                     }
                 }
-                Ok(MsgType::ScriptAlertResponse(ch)) => {
+                Ok(MsgType::ScriptAlertResponse(_, ch)) => {
                     if !script_stdin_write(&mut stdin, ch, true) {
                         break;
                     }
                 }
-                Ok(MsgType::ScriptDone) => {
-                    // The thread must exit to close the script_rx channel
-                    // or there will be multiple receivers fighting for the same events
-                    info!("Script Done received");
-                    cmd.pid.store(0, Ordering::Relaxed);
+                Ok(MsgType::ScriptDone(_)) => {
+                    // The thread must exit to close the script channel
+                    info!("Script {} Done received", id);
                     break;
                 }
                 Ok(_) => (),
@@ -151,32 +306,62 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                 }
             }
         }
+        scripts.lock().unwrap().retain(|elem| elem.id != id);
+        let _ = in_prompt.compare_exchange(id, 0, Ordering::Relaxed, Ordering::Relaxed);
         info!("Script stdin thread done - wait for process termination");
-        child.wait().expect("Wait for script to terminate");
-        info!("Script process terminatated");
+        let status = child.wait().expect("Wait for script to terminate");
+        done.store(true, Ordering::Relaxed);
+        let mut text = format!("{}: {}", endtext, exit_text(&status));
+        if let Some(reason) = reason.lock().unwrap().take() {
+            text = format!("{} ({})", text, reason);
+        }
+        info!("{}", text);
+        let stderr: Vec<String> = tail_rx.recv_timeout(Duration::from_secs(1)).unwrap_or_default();
+        if status.success() {
+            println!("\r{}\r", text);
+        } else {
+            let mut msg = vec![text];
+            msg.extend(stderr.iter().cloned());
+            show_error(msg);
+        }
+        let mut results = results.lock().unwrap();
+        if results.len() >= SCRIPT_RESULTS {
+            results.pop_front();
+        }
+        results.push_back(ScriptResult {
+            id,
+            arg,
+            code: status.code(),
+            status: exit_text(&status),
+            success: status.success(),
+            stderr,
+            end: Instant::now(),
+        });
     });
 
     // Get script stdout and send it to the serial port
-    let binary_mode = cmd.binary_mode.clone();
+    let binary_mode = script_binary.clone();
+    let tx = cmd.tx.clone();
+    let stdout_activity = activity.clone();
     thread::spawn(move || {
         const CR: u8 = 0xd;
         loop {
             let mut buffer = vec![0; 1024];
             match stdout.read(&mut buffer) {
                 Ok(0) => {
-                    println!("\r{}\r", endtext);
-                    info!("{}", endtext);
-                    // Send done to force the stdout thread to exit too
-                    cmd.tx.send(MsgType::ScriptDone).unwrap();
+                    info!("Script {} end of stdout", id);
+                    // Send done to force the stdin thread to exit too
+                    tx.send(MsgType::ScriptDone(id)).unwrap();
                     break;
                 }
                 Ok(cnt) => {
+                    *stdout_activity.lock().unwrap() = Instant::now();
                     if binary_mode.load(Ordering::Relaxed) {
                         trace!("Script_tx (binary): count {}/{}", cnt, buffer.len());
                         for idx in 0..cnt {
                             let val: u8 = buffer[idx];
                             trace!("Script_tx (binary): {:#02x} '{}'", val, val as char);
-                            cmd.tx.send(MsgType::Console(val)).unwrap();
+                            tx.send(MsgType::ScriptOutput(id, val)).unwrap();
                             // thread::sleep(Duration::from_micros(100));
                         }
                     } else {
@@ -186,7 +371,7 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                                 continue;
                             }
                             trace!("Script_tx: {:#02x} '{}'", val, val as char);
-                            cmd.tx.send(MsgType::Console(val)).unwrap();
+                            tx.send(MsgType::ScriptOutput(id, val)).unwrap();
                             echo_tx.send(val).unwrap();
                         }
                     }
@@ -202,9 +387,11 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
 
     // Get script stderr and print it on the console as alert messages
     let in_prompt = cmd.in_prompt.clone();
-    let binary_mode = cmd.binary_mode.clone();
+    let binary_mode = script_binary.clone();
+    let tx = cmd.tx.clone();
     thread::spawn(move || {
         let mut rdr = BufReader::new(stderr);
+        let mut tail: VecDeque<String> = VecDeque::new();
         loop {
             let mut buf = String::new();
             match rdr.read_line(&mut buf) {
@@ -213,11 +400,14 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                     break;
                 }
                 Ok(_) => {
+                    *activity.lock().unwrap() = Instant::now();
                     // First char is a prefix that identifies the type of message
                     let prompt = buf.chars().nth(0).unwrap();
                     // Remove the prefix and the terminating newline
                     let text = String::from(&buf[1..(buf.len()-1)]);
-                    terminal::disable_raw_mode().unwrap();
+                    // There is no terminal in daemon mode and no raw mode in batch mode
+                    let raw = terminal::is_raw_mode_enabled().unwrap_or(false);
+                    let _ = terminal::disable_raw_mode();
                     match prompt {
                         SCRIPT_ALERT => {
                             println!("\n{}", text.with(Color::White).on(Color::DarkMagenta));
@@ -230,7 +420,7 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                             println!("{}", text.with(Color::Black).on(Color::DarkYellow));
                         }
                         SCRIPT_MENU_PROMPT => {
-                            in_prompt.store(true, Ordering::Relaxed);
+                            in_prompt.store(id, Ordering::Relaxed);
                             print!("{}", text.with(Color::Black).on(Color::DarkGreen));
                             io::stdout().flush().unwrap();
                         }
@@ -241,18 +431,26 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                             println!("\nbinary: on");
                             info!("binary on");
                             binary_mode.store(true, Ordering::Relaxed);
+                            tx.send(MsgType::ScriptBinary(id, true)).unwrap();
                         }
                         SCRIPT_BINARY_OFF => {
                             println!("\nbinary: off");
                             info!("binary off");
                             binary_mode.store(false, Ordering::Relaxed);
+                            tx.send(MsgType::ScriptBinary(id, false)).unwrap();
                         }
                         _ => {
                             let text = String::from(&buf[0..(buf.len()-1)]);
-                            println!("{}", text.with(Color::White).on(Color::Black));
+                            println!("{}", text.clone().with(Color::White).on(Color::Black));
+                            if tail.len() >= STDERR_TAIL_LINES {
+                                tail.pop_front();
+                            }
+                            tail.push_back(text);
                         }
                     }
-                    terminal::enable_raw_mode().unwrap();
+                    if raw {
+                        let _ = terminal::enable_raw_mode();
+                    }
                 }
                 Err(e) => {
                     error!("\rScript stderr error: {e:?}");
@@ -260,13 +458,24 @@ fn child_process(cmd: ScriptCommand, mut child: Child) {
                 }
             }
         }
+        let _ = tail_tx.send(tail.into());
         info!("Script stderr thread done");
     });
 }
 
 
 // Execute a script and piping input/output via term server
-pub fn execute_script(cmd: ScriptCommand) {
+// The script is registered before it is started so no process is left behind when the term switch is gone
+pub fn execute_script(cmd: ScriptCommand) -> Result<(), String> {
+    let (id, script_rx) = match register_script(&cmd.tx) {
+        Ok(script) => script,
+        Err(e) => {
+            error!("Could not register script: {e}");
+            let msg = vec![format!("Could not start python script \"{}\"", cmd.arg), format!("Error: \"{}\"", e)];
+            show_error(msg);
+            return Err(e);
+        }
+    };
     // Replace "~" with the home folder for script paths
     let narg = subst_home(&cmd.arg);
     let args = narg.split(" ");
@@ -281,18 +490,39 @@ pub fn execute_script(cmd: ScriptCommand) {
 
     match res {
         Err(err) => {
+            // Release the channel of the script
+            let _ = cmd.tx.send(MsgType::ScriptDone(id));
             let msg = vec![
                 format!("Could not start python script \"{}\"", cmd.arg),
                 format!("Error: \"{}\"", err.to_string()),
             ];
 
             show_error(msg);
+            Err(err.to_string())
         }
         Ok(child) => {
-            let text = format!("Start {} as process id {}", cmd.arg, child.id());
+            let text = format!("Start {} as script {} with process id {}", cmd.arg, id, child.id());
             println!("\r{}\r", text);
             info!("{}", text);
-            child_process(cmd, child);
+            child_process(cmd, id, script_rx, child);
+            Ok(())
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_expiry() {
+        let secs = Duration::from_secs;
+        let timeouts = ScriptTimeouts { timeout: Some(secs(60)), idle: Some(secs(10)) };
+        assert_eq!(expired(&timeouts, secs(30), secs(5)), None);
+        assert_eq!(expired(&timeouts, secs(30), secs(10)), Some(String::from("no output for 10s")));
+        assert_eq!(expired(&timeouts, secs(60), secs(0)), Some(String::from("wall-clock timeout of 60s")));
+        let timeouts = ScriptTimeouts { timeout: None, idle: None };
+        assert_eq!(expired(&timeouts, secs(3600), secs(3600)), None);
+    }
+}
@@ -1,5 +1,5 @@
 //Steen Hegelund
-//Time-Stamp: 2024-Oct-07 15:32
+//Time-Stamp: 2026-Oct-18 19:04
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 //
 // Send and Receive bytes to/from the serial device and the TermSwitch
@@ -9,19 +9,22 @@ use std::thread;
 use std::time::Duration;
 use std::path::PathBuf;
 use std::time::Instant;
+use std::sync::{Arc, atomic::Ordering};
 use log::{error, trace};
 use serialport::SerialPort;
 use crossbeam_channel::{Sender, Receiver};
 use crossterm::style::{Color, Stylize};
 
-use crate::term_switch::{TermSwitch, MsgType};
+use crate::term_switch::{TermSwitch, MsgType, DeviceState};
 
 
-fn run_serial(swi_tx: &Sender<MsgType>, ser_rx: &Receiver<MsgType>, start: Instant, prt: &Box<dyn SerialPort>) -> thread::JoinHandle<()> {
+fn run_serial(swi_tx: &Sender<MsgType>, ser_rx: &Receiver<MsgType>, start: Instant, prt: &Box<dyn SerialPort>,
+              state: &Arc<DeviceState>) -> thread::JoinHandle<()> {
     let mut txport = prt.try_clone().unwrap();
     let mut rxport = prt.try_clone().unwrap();
     let switch_tx = swi_tx.clone();
     let serial_rx = ser_rx.clone();
+    let state = state.clone();
 
     thread::spawn(move || {
         trace!("Wait for console input");
@@ -51,6 +54,13 @@ fn run_serial(swi_tx: &Sender<MsgType>, ser_rx: &Receiver<MsgType>, start: Insta
                         }
                     }
                 }
+                Ok(MsgType::SerialBaudrate(rate)) => {
+                    // Also used when the device is opened again
+                    match txport.set_baud_rate(rate) {
+                        Ok(_) => state.baudrate.store(rate, Ordering::Relaxed),
+                        Err(e) => error!("Baudrate {} Error: {:?}", rate, e),
+                    }
+                }
                 Ok(MsgType::SerialClose) => {
                     trace!("Serial Close");
                     break;
@@ -112,17 +122,21 @@ fn do_open(portname: &str, baudrate: u32) -> Result<Box<dyn SerialPort>, serialp
 pub fn open_device(termswx: &TermSwitch, device: PathBuf, baudrate: u32, keeprunning: bool, start: Instant) {
     let switch_tx = termswx.get_switch_tx();
     let serial_rx = termswx.get_serial_rx();
+    let state = termswx.get_state();
+    state.baudrate.store(baudrate, Ordering::Relaxed);
 
     thread::spawn(move || {
         let portname = device.to_str().unwrap();
         let mut running = true;
         loop {
-            match do_open(portname, baudrate) {
+            match do_open(portname, state.baudrate.load(Ordering::Relaxed)) {
                 Ok(port) => {
                     running = true;
                     show_device_connect(&port);
-                    let handle = run_serial(&switch_tx, &serial_rx, start, &port);
+                    state.connected.store(true, Ordering::Relaxed);
+                    let handle = run_serial(&switch_tx, &serial_rx, start, &port, &state);
                     handle.join().unwrap();
+                    state.connected.store(false, Ordering::Relaxed);
                 }
                 Err(_) => {
                     if !keeprunning {
@@ -0,0 +1,376 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:36
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Transfer files to a target that only has a shell by injecting encoded lines that the shell
+// decodes.  The echo of each line paces the upload and the file is verified using the checksum
+// printed by sha256sum or md5sum on the target.
+
+use crate::term_switch::MsgType;
+use crate::config::{subst_home, ShellTransfer, ShellEncoding, ShellChecksum};
+use crate::console_service::show_error;
+use crate::file_transfer::{TransferPort, spawn_transfer};
+use crate::script_runner::ScriptTable;
+use crate::xmodem::CANCELLED;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crossbeam_channel::Sender;
+
+// Terminates the here-document with the base64 encoded file
+const END_MARKER: &str = "TERMSWX_EOF";
+// Surround a downloaded file: the command line splits them so that its echo does not match
+const DOWNLOAD_BEGIN: &str = "TERMSWX_BEGIN";
+const DOWNLOAD_END: &str = "TERMSWX_END";
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const BASE64_LINE: usize = 57;
+const PRINTF_LINE: usize = 32;
+// Lines sent before waiting for their echo
+const WINDOW: usize = 8;
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+const CHECKSUM_TIMEOUT: Duration = Duration::from_secs(10);
+const CTRL_C: u8 = 0x03;
+
+
+pub struct UploadCommand {
+    pub tx: Sender<MsgType>,
+    pub scripts: ScriptTable,
+    pub local: String,
+    pub remote: String,
+    pub settings: ShellTransfer,
+}
+
+
+pub struct DownloadCommand {
+    pub tx: Sender<MsgType>,
+    pub scripts: ScriptTable,
+    pub remote: String,
+    pub local: String,
+    pub settings: ShellTransfer,
+}
+
+
+// Quote a path for the target shell while keeping "~/" expandable
+pub fn shell_quote(path: &str) -> String {
+    let (prefix, path) = match path.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", path),
+    };
+    format!("{}'{}'", prefix, path.replace('\'', "'\\''"))
+}
+
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|val| format!("{:02x}", val)).collect()
+}
+
+
+// The command that prints the checksum on the target and the expected checksum
+pub fn checksum_command(checksum: ShellChecksum, data: &[u8]) -> Option<(&'static str, String)> {
+    match checksum {
+        ShellChecksum::Sha256 => Some(("sha256sum", to_hex(&Sha256::digest(data)))),
+        ShellChecksum::Md5 => Some(("md5sum", to_hex(&Md5::digest(data)))),
+        ShellChecksum::Disabled => None,
+    }
+}
+
+
+// Find a complete word of hex digits with a given length in the output from the target
+pub fn find_hex(text: &str, len: usize) -> Option<String> {
+    let mut words: Vec<&str> = text.split_whitespace().collect();
+    if !text.ends_with(char::is_whitespace) {
+        words.pop();
+    }
+    words.into_iter()
+        .find(|word| word.len() == len && word.chars().all(|ch| ch.is_ascii_hexdigit()))
+        .map(|word| word.to_lowercase())
+}
+
+
+// Collect the output from the target until the text is complete or the time is up
+pub fn read_output(port: &mut dyn TransferPort, timeout: Duration, done: impl Fn(&str) -> bool)
+                   -> Result<String, String> {
+    let start = Instant::now();
+    let mut output = Vec::new();
+    while start.elapsed() < timeout {
+        if port.cancelled() {
+            return Err(String::from(CANCELLED));
+        }
+        if let Some(val) = port.read(Duration::from_millis(100)) {
+            output.push(val);
+            if val.is_ascii_whitespace() && done(&String::from_utf8_lossy(&output)) {
+                break;
+            }
+        }
+    }
+    Ok(String::from_utf8_lossy(&output).to_string())
+}
+
+
+// The shell commands that recreate the file and how much of the file is written after each line
+fn upload_lines(data: &[u8], target: &str, encoding: ShellEncoding) -> Vec<(String, usize)> {
+    let mut lines = Vec::new();
+    match encoding {
+        ShellEncoding::Base64 => {
+            lines.push((format!("base64 -d > {} << '{}'", target, END_MARKER), 0));
+            for (idx, chunk) in data.chunks(BASE64_LINE).enumerate() {
+                lines.push((STANDARD.encode(chunk), idx * BASE64_LINE + chunk.len()));
+            }
+            lines.push((String::from(END_MARKER), data.len()));
+        }
+        ShellEncoding::Printf => {
+            lines.push((format!(": > {}", target), 0));
+            for (idx, chunk) in data.chunks(PRINTF_LINE).enumerate() {
+                let text: String = chunk.iter().map(|val| format!("\\{:03o}", val)).collect();
+                lines.push((format!("printf '{}' >> {}", text, target), idx * PRINTF_LINE + chunk.len()));
+            }
+        }
+    }
+    lines
+}
+
+
+fn wait_echo(port: &mut dyn TransferPort) -> Result<(), String> {
+    let start = Instant::now();
+    while start.elapsed() < ECHO_TIMEOUT {
+        if port.cancelled() {
+            return Err(String::from(CANCELLED));
+        }
+        if port.read(Duration::from_millis(100)) == Some(b'\n') {
+            return Ok(());
+        }
+    }
+    Err(String::from("The target did not echo the uploaded lines"))
+}
+
+
+// Send the lines while keeping a few of them in flight
+fn send_lines(port: &mut dyn TransferPort, name: &str, lines: &[(String, usize)], total: usize) -> Result<(), String> {
+    let mut echoed = 0;
+    for (idx, (line, done)) in lines.iter().enumerate() {
+        while idx - echoed >= WINDOW {
+            wait_echo(port)?;
+            echoed += 1;
+        }
+        port.write(line.as_bytes());
+        port.write(b"\r");
+        port.progress(name, *done as u64, Some(total as u64));
+    }
+    while echoed < lines.len() {
+        wait_echo(port)?;
+        echoed += 1;
+    }
+    Ok(())
+}
+
+
+// Compare the checksum of the file on the target with the local file
+pub fn verify(port: &mut dyn TransferPort, target: &str, data: &[u8], checksum: ShellChecksum)
+              -> Result<String, String> {
+    let Some((command, expected)) = checksum_command(checksum, data) else {
+        return Ok(String::from("not verified"));
+    };
+    port.purge();
+    port.write(format!("{} {}\r", command, target).as_bytes());
+    let output = read_output(port, CHECKSUM_TIMEOUT, |text| find_hex(text, expected.len()).is_some())?;
+    match find_hex(&output, expected.len()) {
+        Some(found) if found == expected => Ok(format!("{} verified", command)),
+        Some(found) => Err(format!("{} mismatch: {} on the target but {} expected", command, found, expected)),
+        None => Err(format!("Could not get the {} output from the target", command)),
+    }
+}
+
+
+fn upload(port: &mut dyn TransferPort, name: &str, data: &[u8], remote: &str, settings: ShellTransfer)
+          -> Result<String, String> {
+    let target = shell_quote(remote);
+    port.purge();
+    let lines = upload_lines(data, &target, settings.encoding);
+    if let Err(e) = send_lines(port, name, &lines, data.len()) {
+        // Stop the shell command on the target
+        port.write(&[CTRL_C]);
+        return Err(e);
+    }
+    let result = verify(port, &target, data, settings.checksum)?;
+    Ok(format!("Uploaded {} bytes to {} ({})", data.len(), remote, result))
+}
+
+
+// Start an upload in its own thread
+pub fn start_upload(cmd: UploadCommand) {
+    let path = subst_home(&cmd.local);
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            show_error(vec![format!("Could not read file \"{}\"", path), format!("Error: \"{}\"", e)]);
+            return;
+        }
+    };
+    let name = Path::new(&path).file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+    let arg = format!("Upload {} to {}", path, cmd.remote);
+    let remote = cmd.remote;
+    let settings = cmd.settings;
+    spawn_transfer(cmd.tx, cmd.scripts, arg, move |port| upload(port, &name, &data, &remote, settings));
+}
+
+
+// Print the size and the content of the file between markers: od is used when there is no base64 program
+fn download_command(target: &str, encoding: ShellEncoding) -> String {
+    let dump = match encoding {
+        ShellEncoding::Base64 => format!("base64 {}", target),
+        ShellEncoding::Printf => format!("od -An -v -tx1 {}", target),
+    };
+    let (begin, end) = (DOWNLOAD_BEGIN.replace('_', "_\"\""), DOWNLOAD_END.replace('_', "_\"\""));
+    format!("echo {} $(wc -c < {}); {}; echo {}", begin, target, dump, end)
+}
+
+
+// Collect the lines between the markers and the size printed after the begin marker
+fn capture_download(port: &mut dyn TransferPort, name: &str, encoding: ShellEncoding)
+                    -> Result<(Vec<String>, Option<u64>), String> {
+    let mut line = Vec::new();
+    let mut lines = Vec::new();
+    let mut size = None;
+    let mut started = false;
+    let mut received = 0;
+    let mut last = Instant::now();
+    loop {
+        if port.cancelled() {
+            return Err(String::from(CANCELLED));
+        }
+        let Some(val) = port.read(Duration::from_millis(100)) else {
+            if last.elapsed() > IDLE_TIMEOUT {
+                return Err(String::from("The target did not send the file"));
+            }
+            continue;
+        };
+        last = Instant::now();
+        if val != b'\n' {
+            line.push(val);
+            continue;
+        }
+        let text = String::from_utf8_lossy(&line).trim().to_string();
+        line.clear();
+        if !started {
+            if let Some(pos) = text.find(DOWNLOAD_BEGIN) {
+                started = true;
+                size = text[pos + DOWNLOAD_BEGIN.len()..].trim().parse().ok();
+            }
+        } else if text.contains(DOWNLOAD_END) {
+            return Ok((lines, size));
+        } else {
+            received += match encoding {
+                ShellEncoding::Base64 => text.len() as u64 * 3 / 4,
+                ShellEncoding::Printf => text.split_whitespace().count() as u64,
+            };
+            port.progress(name, received, size);
+            lines.push(text);
+        }
+    }
+}
+
+
+fn decode_download(lines: &[String], encoding: ShellEncoding) -> Result<Vec<u8>, String> {
+    let data = match encoding {
+        ShellEncoding::Base64 => STANDARD.decode(lines.concat()).ok(),
+        ShellEncoding::Printf => lines.iter().flat_map(|line| line.split_whitespace())
+            .map(|word| u8::from_str_radix(word, 16).ok())
+            .collect(),
+    };
+    // The target prints an error message instead of the file
+    data.ok_or_else(|| format!("Could not decode the file: {}", lines.first().cloned().unwrap_or_default()))
+}
+
+
+fn download(port: &mut dyn TransferPort, remote: &str, local: &str, settings: ShellTransfer)
+            -> Result<String, String> {
+    let target = shell_quote(remote);
+    let name = Path::new(remote).file_name().and_then(|name| name.to_str()).unwrap_or(remote);
+    port.purge();
+    port.write(download_command(&target, settings.encoding).as_bytes());
+    port.write(b"\r");
+    let (lines, size) = capture_download(port, name, settings.encoding)?;
+    let data = decode_download(&lines, settings.encoding)?;
+    if size.is_some_and(|size| size != data.len() as u64) {
+        return Err(format!("Received {} bytes but the file has {} bytes", data.len(), size.unwrap_or_default()));
+    }
+    let result = verify(port, &target, &data, settings.checksum)?;
+    let mut filename = PathBuf::from(local);
+    if filename.is_dir() {
+        filename.push(name);
+    }
+    std::fs::write(&filename, &data).map_err(|e| format!("Could not write {:?}: {}", filename, e))?;
+    Ok(format!("Downloaded {} bytes from {} to {:?} ({})", data.len(), remote, filename, result))
+}
+
+
+// Start a download in its own thread
+pub fn start_download(cmd: DownloadCommand) {
+    let local = subst_home(&cmd.local);
+    let arg = format!("Download {} to {}", cmd.remote, local);
+    let remote = cmd.remote;
+    let settings = cmd.settings;
+    spawn_transfer(cmd.tx, cmd.scripts, arg, move |port| download(port, &remote, &local, settings));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::test_data;
+
+    #[test]
+    fn quote_paths() {
+        assert_eq!(shell_quote("/tmp/my file"), "'/tmp/my file'");
+        assert_eq!(shell_quote("~/it's"), "~/'it'\\''s'");
+    }
+
+    #[test]
+    fn checksum_in_output() {
+        let data = b"hello\n";
+        let (command, expected) = checksum_command(ShellChecksum::Md5, data).unwrap();
+        assert_eq!((command, expected.as_str()), ("md5sum", "b1946ac92492d2347c6235b4d2611184"));
+        let output = format!("md5sum 'hello.txt'\r\n{}  hello.txt\r\n# ", expected.to_uppercase());
+        assert_eq!(find_hex(&output, expected.len()), Some(expected.clone()));
+        // Incomplete output
+        assert_eq!(find_hex(&output[..40], expected.len()), None);
+    }
+
+    #[test]
+    fn base64_lines() {
+        let data = test_data(200);
+        let lines = upload_lines(&data, "'x'", ShellEncoding::Base64);
+        assert_eq!(lines.first().unwrap().0, "base64 -d > 'x' << 'TERMSWX_EOF'");
+        assert_eq!(lines.last().unwrap(), &(String::from(END_MARKER), 200));
+        let decoded: Vec<u8> = lines[1..lines.len() - 1].iter().flat_map(|(line, _)| STANDARD.decode(line).unwrap())
+            .collect();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn download_markers() {
+        let command = download_command("'x'", ShellEncoding::Base64);
+        assert_eq!(command, "echo TERMSWX_\"\"BEGIN $(wc -c < 'x'); base64 'x'; echo TERMSWX_\"\"END");
+        assert!(!command.contains(DOWNLOAD_BEGIN) && !command.contains(DOWNLOAD_END));
+    }
+
+    #[test]
+    fn decode_dumps() {
+        let lines = vec![String::from("AAEC"), String::from("/w==")];
+        assert_eq!(decode_download(&lines, ShellEncoding::Base64), Ok(vec![0, 1, 2, 0xff]));
+        let lines = vec![String::from("00 01 02"), String::from("ff")];
+        assert_eq!(decode_download(&lines, ShellEncoding::Printf), Ok(vec![0, 1, 2, 0xff]));
+        let lines = vec![String::from("base64: x: No such file or directory")];
+        assert!(decode_download(&lines, ShellEncoding::Base64).unwrap_err().contains("No such file"));
+    }
+
+    #[test]
+    fn printf_lines() {
+        let lines = upload_lines(&[0, 0x27, 0xff], "'x'", ShellEncoding::Printf);
+        assert_eq!(lines, vec![(String::from(": > 'x'"), 0), (String::from("printf '\\000\\047\\377' >> 'x'"), 3)]);
+    }
+}
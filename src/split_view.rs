@@ -0,0 +1,388 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:01
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Show the output of several devices in panes side by side
+// A terminal scroll region always spans the full width, so each pane keeps its output as plain text lines
+// that are scrolled here and redrawn with cursor positioning
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossterm::{cursor, queue, terminal};
+use crossterm::style::{Color, Print, Stylize};
+
+pub const MAX_PANES: usize = 4;
+// Lines kept per pane so a larger terminal can show more of the output after a resize
+const MAX_LINES: usize = 500;
+const RENDER_INTERVAL: Duration = Duration::from_millis(40);
+// Output from other threads can end up on top of the panes: repair it now and then
+const FULL_RENDER_INTERVAL: Duration = Duration::from_secs(2);
+
+pub type SharedView = Arc<Mutex<Option<SplitView>>>;
+
+
+// Screen area of a pane: the first row is the title
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Area {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+
+// Two panes side by side, three or four panes in two rows with a single pane using the full width
+fn layout(count: usize, width: u16, height: u16) -> Vec<Area> {
+    let rows: Vec<usize> = match count {
+        0 => vec![],
+        1 => vec![1],
+        2 => vec![2],
+        3 => vec![2, 1],
+        _ => vec![2, 2],
+    };
+    let mut areas = Vec::new();
+    let row_height = height / rows.len().max(1) as u16;
+    for (row, cols) in rows.iter().enumerate() {
+        let y = row as u16 * row_height;
+        let pane_height = if row + 1 == rows.len() { height - y } else { row_height };
+        // One column between the panes for the separator
+        let pane_width = (width.saturating_sub(*cols as u16 - 1)) / *cols as u16;
+        for col in 0..*cols {
+            let x = col as u16 * (pane_width + 1);
+            let pane_width = if col + 1 == *cols { width - x } else { pane_width };
+            areas.push(Area { x, y, width: pane_width, height: pane_height });
+        }
+    }
+    areas
+}
+
+
+// Escape sequence parser state: only the sequences that change the text of the current line are used
+#[derive(Debug, PartialEq)]
+enum Escape {
+    None,
+    Start,
+    Csi(String),
+}
+
+
+// The output of a device as lines of text and the cursor column in the last line
+struct Pane {
+    device: usize,
+    title: String,
+    lines: VecDeque<Vec<char>>,
+    col: usize,
+    width: usize,
+    escape: Escape,
+    utf8: Vec<u8>,
+    dirty: bool,
+}
+
+impl Pane {
+    fn new(device: usize, title: String, width: usize) -> Pane {
+        Pane {
+            device,
+            title,
+            lines: VecDeque::from([Vec::new()]),
+            col: 0,
+            width: width.max(1),
+            escape: Escape::None,
+            utf8: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    fn push(&mut self, val: u8) {
+        self.dirty = true;
+        match &mut self.escape {
+            Escape::None => (),
+            Escape::Start => {
+                self.escape = if val == b'[' { Escape::Csi(String::new()) } else { Escape::None };
+                return;
+            }
+            Escape::Csi(params) => {
+                if (0x40..=0x7e).contains(&val) {
+                    let params = std::mem::take(params);
+                    self.escape = Escape::None;
+                    self.control(&params, val as char);
+                } else {
+                    params.push(val as char);
+                }
+                return;
+            }
+        }
+        if val >= 0x80 {
+            // Collect the bytes of a UTF-8 character
+            self.utf8.push(val);
+            if let Ok(text) = std::str::from_utf8(&self.utf8) {
+                let ch = text.chars().next().unwrap_or('?');
+                self.utf8.clear();
+                self.put(ch);
+            } else if self.utf8.len() >= 4 {
+                self.utf8.clear();
+                self.put('?');
+            }
+            return;
+        }
+        self.utf8.clear();
+        match val {
+            0x1b => self.escape = Escape::Start,
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\t' => self.col = ((self.col / 8 + 1) * 8).min(self.width - 1),
+            0x20..=0x7e => self.put(val as char),
+            _ => (),
+        }
+    }
+
+    // Cursor movement on the line and erasing of the line or the pane
+    fn control(&mut self, params: &str, cmd: char) {
+        let count = params.parse::<usize>().unwrap_or(1).max(1);
+        let line = self.lines.back_mut().unwrap();
+        match cmd {
+            'C' => self.col = (self.col + count).min(self.width - 1),
+            'D' => self.col = self.col.saturating_sub(count),
+            'G' => self.col = (count - 1).min(self.width - 1),
+            'K' => match params {
+                "1" => line.iter_mut().take(self.col + 1).for_each(|ch| *ch = ' '),
+                "2" => line.clear(),
+                _ => line.truncate(self.col),
+            },
+            'J' if params == "2" || params == "3" => {
+                self.lines = VecDeque::from([Vec::new()]);
+                self.col = 0;
+            }
+            _ => (),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.lines.push_back(Vec::new());
+        if self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    fn put(&mut self, ch: char) {
+        if self.col >= self.width {
+            self.newline();
+            self.col = 0;
+        }
+        let line = self.lines.back_mut().unwrap();
+        if line.len() < self.col {
+            line.resize(self.col, ' ');
+        }
+        if self.col < line.len() {
+            line[self.col] = ch;
+        } else {
+            line.push(ch);
+        }
+        self.col += 1;
+    }
+
+    // The last lines that fit in the pane
+    fn visible(&self, rows: usize) -> impl Iterator<Item = &Vec<char>> {
+        self.lines.iter().skip(self.lines.len().saturating_sub(rows))
+    }
+}
+
+
+// The panes and the pane that has the keyboard focus
+pub struct SplitView {
+    panes: Vec<Pane>,
+    focus: usize,
+    paused: bool,
+    size: (u16, u16),
+    last_full: Instant,
+}
+
+impl SplitView {
+    // The devices as pairs of device index and title: the first pane gets the focus
+    pub fn new(devices: Vec<(usize, String)>) -> SplitView {
+        let size = terminal::size().unwrap_or((80, 24));
+        let areas = layout(devices.len(), size.0, size.1);
+        let panes = zip_areas(devices, &areas);
+        SplitView {
+            panes,
+            focus: 0,
+            paused: false,
+            size,
+            last_full: Instant::now(),
+        }
+    }
+
+    pub fn devices(&self) -> Vec<usize> {
+        self.panes.iter().map(|pane| pane.device).collect()
+    }
+
+    // Add output to the pane of a device: false if the device has no pane
+    pub fn push(&mut self, device: usize, val: u8) -> bool {
+        match self.panes.iter_mut().find(|pane| pane.device == device) {
+            Some(pane) => {
+                pane.push(val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Move the focus to the pane of a device and return true if it has a pane
+    pub fn focus_device(&mut self, device: usize) -> bool {
+        match self.panes.iter().position(|pane| pane.device == device) {
+            Some(pos) => {
+                self.set_focus(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Move the focus forward or backward and return the device with the focus
+    pub fn move_focus(&mut self, forward: bool) -> usize {
+        let count = self.panes.len();
+        let pos = if forward { (self.focus + 1) % count } else { (self.focus + count - 1) % count };
+        self.set_focus(pos);
+        self.panes[pos].device
+    }
+
+    fn set_focus(&mut self, pos: usize) {
+        self.panes[self.focus].dirty = true;
+        self.focus = pos;
+        self.panes[pos].dirty = true;
+    }
+
+    // Stop drawing while another screen such as the help menu is shown
+    pub fn pause(&mut self, paused: bool) {
+        self.paused = paused;
+        self.last_full = Instant::now() - FULL_RENDER_INTERVAL;
+    }
+
+    fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let size = terminal::size().unwrap_or(self.size);
+        let full = size != self.size || self.last_full.elapsed() >= FULL_RENDER_INTERVAL;
+        if !full && self.panes.iter().all(|pane| !pane.dirty) {
+            return Ok(());
+        }
+        if size != self.size {
+            queue!(out, terminal::Clear(terminal::ClearType::All))?;
+            self.size = size;
+        }
+        if full {
+            self.last_full = Instant::now();
+        }
+        let areas = layout(self.panes.len(), size.0, size.1);
+        queue!(out, cursor::Hide)?;
+        for (pos, (pane, area)) in self.panes.iter_mut().zip(areas.iter()).enumerate() {
+            if !full && !pane.dirty {
+                continue;
+            }
+            pane.dirty = false;
+            pane.width = usize::from(area.width).max(1);
+            let width = usize::from(area.width);
+            let title = format!(" {}: {}", pane.device + 1, pane.title);
+            let title: String = format!("{:width$}", title, width = width).chars().take(width).collect();
+            let title = if pos == self.focus {
+                title.with(Color::White).on(Color::DarkBlue)
+            } else {
+                title.with(Color::Black).on(Color::Grey)
+            };
+            queue!(out, cursor::MoveTo(area.x, area.y), Print(title))?;
+            let rows = usize::from(area.height.saturating_sub(1));
+            let mut lines = pane.visible(rows);
+            for row in 0..rows {
+                let text: String = match lines.next() {
+                    Some(line) => line.iter().take(width).collect(),
+                    None => String::new(),
+                };
+                queue!(out, cursor::MoveTo(area.x, area.y + 1 + row as u16), Print(format!("{:width$}", text, width = width)))?;
+            }
+            // The separator in the column left of the pane
+            if area.x > 0 {
+                for row in 0..area.height {
+                    queue!(out, cursor::MoveTo(area.x - 1, area.y + row), Print('│'))?;
+                }
+            }
+        }
+        // Leave the cursor where the focused device writes next
+        if let (Some(pane), Some(area)) = (self.panes.get(self.focus), areas.get(self.focus)) {
+            let rows = usize::from(area.height.saturating_sub(1)).max(1);
+            let row = pane.lines.len().min(rows) - 1;
+            let col = pane.col.min(usize::from(area.width.saturating_sub(1)));
+            queue!(out, cursor::MoveTo(area.x + col as u16, area.y + 1 + row as u16), cursor::Show)?;
+        }
+        out.flush()
+    }
+}
+
+
+fn zip_areas(devices: Vec<(usize, String)>, areas: &[Area]) -> Vec<Pane> {
+    devices.into_iter().zip(areas.iter())
+        .map(|((device, title), area)| Pane::new(device, title, usize::from(area.width)))
+        .collect()
+}
+
+
+// Draw the changed panes while the split view is active
+pub fn start_render(view: SharedView) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(RENDER_INTERVAL);
+            if let Some(split) = view.lock().unwrap().as_mut() {
+                if !split.paused {
+                    let _ = split.render(&mut io::stdout());
+                }
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(pane: &Pane) -> Vec<String> {
+        pane.lines.iter().map(|line| line.iter().collect()).collect()
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(layout(2, 81, 24), vec![
+            Area { x: 0, y: 0, width: 40, height: 24 },
+            Area { x: 41, y: 0, width: 40, height: 24 },
+        ]);
+        assert_eq!(layout(3, 80, 25), vec![
+            Area { x: 0, y: 0, width: 39, height: 12 },
+            Area { x: 40, y: 0, width: 40, height: 12 },
+            Area { x: 0, y: 12, width: 80, height: 13 },
+        ]);
+        assert_eq!(layout(4, 80, 24).len(), 4);
+    }
+
+    #[test]
+    fn pane_text() {
+        let mut pane = Pane::new(0, String::from("board"), 12);
+        for ch in b"dev# ls\r\nbin\tetc\r\n0123456789abcd".iter() {
+            pane.push(*ch);
+        }
+        assert_eq!(text(&pane), vec!["dev# ls", "bin     etc", "0123456789ab", "cd"]);
+        assert_eq!(pane.col, 2);
+    }
+
+    #[test]
+    fn pane_escapes() {
+        let mut pane = Pane::new(0, String::from("board"), 40);
+        for ch in "\x1b[?2004hdev# abc\x08\x08\x1b[Kxy \x1b[1;32mgrün\x1b[0m".bytes() {
+            pane.push(ch);
+        }
+        assert_eq!(text(&pane), vec!["dev# axy grün"]);
+        for ch in b"\x1b[2J".iter() {
+            pane.push(*ch);
+        }
+        assert_eq!(text(&pane), vec![""]);
+    }
+}
@@ -0,0 +1,309 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:20
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Show the state of the active device on the last terminal row
+// The scroll region excludes the last row so the device output does not overwrite the status bar
+
+use crate::term_switch::TermSwitch;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, atomic::AtomicUsize, atomic::Ordering};
+use std::thread;
+use std::time::Duration;
+use crossterm::{cursor, queue, terminal};
+use crossterm::style::{Color, Print, Stylize};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+// The serial service opens the ports with 8 data bits, no parity and 1 stop bit
+const LINE_SETTINGS: &str = "8N1";
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+pub type SharedStatus = Arc<Mutex<Option<StatusBar>>>;
+
+
+// A device as shown in the status bar
+#[derive(Clone)]
+pub struct StatusDevice {
+    pub name: String,
+    pub path: String,
+    pub networkdev: bool,
+    pub termswx: TermSwitch,
+}
+
+
+// The values shown in the status bar
+#[derive(Debug, Default, PartialEq)]
+struct StatusInfo {
+    number: usize,
+    count: usize,
+    name: String,
+    path: String,
+    baudrate: u32,
+    connected: bool,
+    clients: i8,
+    scripts: Vec<String>,
+    binary: bool,
+    log: Option<String>,
+    received: u64,
+    sent: u64,
+}
+
+
+// Where the device output is in an escape sequence: the status bar is only drawn between them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Start,
+    Csi,
+    Text,
+}
+
+
+fn collect_info(devices: &[StatusDevice], active: usize) -> StatusInfo {
+    let device = &devices[active];
+    let state = device.termswx.get_state();
+    StatusInfo {
+        number: active + 1,
+        count: devices.len(),
+        name: device.name.clone(),
+        path: device.path.clone(),
+        baudrate: if device.networkdev { 0 } else { state.baudrate.load(Ordering::Relaxed) },
+        connected: state.connected.load(Ordering::Relaxed),
+        clients: device.termswx.get_clients().load(Ordering::Relaxed),
+        scripts: device.termswx.get_scripts().lock().unwrap().iter().map(|script| script.arg.clone()).collect(),
+        binary: device.termswx.get_binary_mode().load(Ordering::Relaxed),
+        log: state.log_path(),
+        received: state.received.load(Ordering::Relaxed),
+        sent: state.sent.load(Ordering::Relaxed),
+    }
+}
+
+
+// The text is cut or padded to the terminal width
+fn status_text(info: &StatusInfo, width: usize) -> String {
+    let mut fields = Vec::new();
+    if info.count > 1 {
+        fields.push(format!("{}/{} {}", info.number, info.count, info.name));
+    } else {
+        fields.push(info.name.clone());
+    }
+    if info.baudrate > 0 {
+        fields.push(format!("{} {} {}", info.path, info.baudrate, LINE_SETTINGS));
+    } else {
+        fields.push(info.path.clone());
+    }
+    fields.push(String::from(if info.connected { "connected" } else { "disconnected" }));
+    if info.clients > 0 {
+        fields.push(format!("clients {}", info.clients));
+    }
+    if !info.scripts.is_empty() {
+        fields.push(format!("script {}", info.scripts.join(", ")));
+    }
+    if info.binary {
+        fields.push(String::from("binary"));
+    }
+    if let Some(path) = &info.log {
+        fields.push(format!("log {}", path));
+    }
+    fields.push(format!("rx {} tx {}", info.received, info.sent));
+    let text: String = format!(" {}", fields.join(" | ")).chars().take(width).collect();
+    format!("{:<width$}", text, width = width)
+}
+
+
+pub struct StatusBar {
+    devices: Vec<StatusDevice>,
+    active: Arc<AtomicUsize>,
+    paused: bool,
+    size: (u16, u16),
+    escape: Escape,
+    // Remaining bytes of a UTF-8 character
+    pending: u8,
+    // The device output removed the scroll region
+    reset: bool,
+}
+
+impl StatusBar {
+    pub fn new(devices: Vec<StatusDevice>, active: Arc<AtomicUsize>, paused: bool) -> StatusBar {
+        StatusBar {
+            devices,
+            active,
+            paused,
+            size: (0, 0),
+            escape: Escape::None,
+            pending: 0,
+            reset: false,
+        }
+    }
+
+    // Track the device output before it is written to the terminal
+    pub fn output(&mut self, ch: u8) {
+        if self.pending > 0 && ch & 0xc0 == 0x80 {
+            self.pending -= 1;
+            return;
+        }
+        self.pending = 0;
+        self.escape = match (self.escape, ch) {
+            (Escape::Text, BEL) => Escape::None,
+            (Escape::Text, ESC) => Escape::Start,
+            (Escape::Text, _) => Escape::Text,
+            (_, ESC) => Escape::Start,
+            (Escape::Start, b'[') => Escape::Csi,
+            (Escape::Start, b']' | b'P' | b'_' | b'^') => Escape::Text,
+            (Escape::Start, b'c') => {
+                self.reset = true;
+                Escape::None
+            }
+            (Escape::Start, _) => Escape::None,
+            (Escape::Csi, b'r') => {
+                self.reset = true;
+                Escape::None
+            }
+            (Escape::Csi, 0x40..=0x7e) => Escape::None,
+            (Escape::Csi, _) => Escape::Csi,
+            (Escape::None, 0xc0..=0xdf) => {
+                self.pending = 1;
+                Escape::None
+            }
+            (Escape::None, 0xe0..=0xef) => {
+                self.pending = 2;
+                Escape::None
+            }
+            (Escape::None, 0xf0..=0xf7) => {
+                self.pending = 3;
+                Escape::None
+            }
+            (Escape::None, _) => Escape::None,
+        };
+    }
+
+    fn between_output(&self) -> bool {
+        self.escape == Escape::None && self.pending == 0
+    }
+
+    // Keep the last row out of the scroll region: the cursor stays on the same line of output
+    fn set_region(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.size = terminal::size()?;
+        queue!(out, Print("\n"), cursor::MoveUp(1), cursor::SavePosition,
+               Print(format!("\x1b[1;{}r", self.size.1.saturating_sub(1))), cursor::RestorePosition)?;
+        self.reset = false;
+        Ok(())
+    }
+
+    pub fn show(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.set_region(out)?;
+        self.render(out)
+    }
+
+    // Give the full screen back to the output
+    pub fn hide(&mut self, out: &mut impl Write) -> io::Result<()> {
+        queue!(out, cursor::SavePosition, Print("\x1b[r"), cursor::MoveTo(0, self.size.1.saturating_sub(1)),
+               terminal::Clear(terminal::ClearType::CurrentLine), cursor::RestorePosition)?;
+        out.flush()
+    }
+
+    // The split view and the help menu use the full screen
+    pub fn pause(&mut self, paused: bool, out: &mut impl Write) -> io::Result<()> {
+        self.paused = paused;
+        if paused {
+            self.hide(out)
+        } else {
+            self.show(out)
+        }
+    }
+
+    fn render(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.size != terminal::size()? || self.reset {
+            self.set_region(out)?;
+        }
+        let active = self.active.load(Ordering::Relaxed);
+        let text = status_text(&collect_info(&self.devices, active), self.size.0 as usize);
+        queue!(out, cursor::SavePosition, cursor::MoveTo(0, self.size.1.saturating_sub(1)),
+               Print(text.with(Color::White).on(Color::DarkBlue)), cursor::RestorePosition)?;
+        out.flush()
+    }
+}
+
+
+// The rows of the terminal that the device output can use
+pub fn output_rows(status: &SharedStatus, rows: u16) -> u16 {
+    match status.lock().unwrap().as_ref() {
+        Some(bar) if !bar.paused => rows.saturating_sub(1),
+        _ => rows,
+    }
+}
+
+
+pub fn start_refresh(status: SharedStatus) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(REFRESH_INTERVAL);
+            if let Some(bar) = status.lock().unwrap().as_mut() {
+                if !bar.paused && bar.between_output() {
+                    let _ = bar.render(&mut io::stdout());
+                }
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let mut info = StatusInfo {
+            number: 2,
+            count: 3,
+            name: String::from("board2"),
+            path: String::from("/dev/ttyUSB1"),
+            baudrate: 115200,
+            connected: true,
+            received: 1200,
+            sent: 34,
+            ..Default::default()
+        };
+        assert_eq!(status_text(&info, 70),
+                   " 2/3 board2 | /dev/ttyUSB1 115200 8N1 | connected | rx 1200 tx 34     ");
+        info.count = 1;
+        info.baudrate = 0;
+        info.path = String::from("lab:7300");
+        info.clients = 2;
+        info.scripts = vec![String::from("test.py")];
+        info.binary = true;
+        info.log = Some(String::from("/tmp/b.log"));
+        assert_eq!(status_text(&info, 200).trim_end(),
+                   " board2 | lab:7300 | connected | clients 2 | script test.py | binary | log /tmp/b.log | rx 1200 tx 34");
+        assert_eq!(status_text(&info, 19), " board2 | lab:7300 ");
+    }
+
+    #[test]
+    fn escapes() {
+        let mut bar = StatusBar::new(Vec::new(), Arc::new(AtomicUsize::new(0)), false);
+        for ch in b"\x1b[1;3" {
+            bar.output(*ch);
+        }
+        assert!(!bar.between_output());
+        bar.output(b'm');
+        assert!(bar.between_output());
+        for ch in b"\x1b]0;title" {
+            bar.output(*ch);
+        }
+        assert!(!bar.between_output());
+        bar.output(BEL);
+        assert!(bar.between_output());
+        bar.output(0xc3);
+        assert!(!bar.between_output());
+        bar.output(0xa6);
+        assert!(bar.between_output());
+        assert!(!bar.reset);
+        for ch in b"\x1b[r" {
+            bar.output(*ch);
+        }
+        assert!(bar.reset && bar.between_output());
+    }
+}
@@ -1,48 +1,215 @@
 //Steen Hegelund
-//Time-Stamp: 2026-Jan-31 12:26
+//Time-Stamp: 2026-Oct-18 19:42
 //vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
 
 use log::{error, trace, info};
 use std::thread;
-use crossbeam_channel::{unbounded, Sender, Receiver};
-use std::sync::{Arc, atomic::AtomicU32, atomic::AtomicBool, atomic::AtomicI8, atomic::Ordering};
+use crossbeam_channel::{bounded, unbounded, Sender, Receiver};
+use std::sync::{Arc, Mutex, atomic::AtomicBool, atomic::AtomicI8, atomic::AtomicU32, atomic::AtomicU64, atomic::Ordering};
 use std::net::SocketAddr;
+use std::time::Instant;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use crossterm::style::{Color, Stylize};
+use crate::config::{ClientRole, ScriptRoute, ScriptRouting};
+use crate::script_runner::{ScriptTable, ScriptResults};
+
+// Script ids are unique across the devices so a script prompt is answered by the script that asked
+static NEXT_SCRIPT_ID: AtomicU32 = AtomicU32::new(1);
 
 // Messages sent via channels between threads
 #[derive(Debug,Clone)]
 pub enum MsgType {
-    Add(SocketAddr),
+    Add(SocketAddr, ClientRole),
     Added(Receiver<MsgType>),
     Console(u8),
+    NetInput(SocketAddr, u8),
+    TakeControl(Option<SocketAddr>),
     Serial(u8),
     SerialClose,
     SerialBreak,
-    ScriptAlertResponse(u8),
-    ScriptDone,
+    SerialBaudrate(u32),
+    ScriptAdd(Sender<MsgType>),
+    ScriptAdded(u32, Receiver<MsgType>),
+    ScriptOutput(u32, u8),
+    ScriptBinary(u32, bool),
+    ScriptAlertResponse(u32, u8),
+    ScriptDone(u32),
     NetClientExit(SocketAddr),
+    NetClientKick(SocketAddr),
+    Show(usize),
+    History(Sender<Vec<u8>>),
     Exit,
 }
 
 
+// A connected remote client as shown in the help menu
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub addr: SocketAddr,
+    pub role: ClientRole,
+    pub connected: chrono::DateTime<chrono::Local>,
+    pub start: Instant,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub write_lock: AtomicBool,
+}
+
+pub type ClientTable = Arc<Mutex<Vec<Arc<ClientInfo>>>>;
+
+
 #[derive(Debug)]
 struct NetClient {
     addr: SocketAddr,
     tx: Sender<MsgType>,
+    info: Arc<ClientInfo>,
+    // Told that its input is ignored since the write lock changed
+    warned: bool,
+}
+
+
+// Publish the connected clients to the console and the server
+#[derive(Clone)]
+struct ClientRegistry {
+    count: Arc<AtomicI8>,
+    table: ClientTable,
+}
+
+impl ClientRegistry {
+    fn update(&self, net_clients: &[NetClient]) {
+        self.count.store(net_clients.len().try_into().expect("Get number of clients"), Ordering::Relaxed);
+        *self.table.lock().unwrap() = net_clients.iter().map(|elem| elem.info.clone()).collect();
+    }
+}
+
+
+// Who may send input to the device: all writers or only the holder of the write lock
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WriteLock {
+    Shared,
+    Local,
+    Remote(SocketAddr),
+}
+
+impl WriteLock {
+    fn holder(origin: Option<SocketAddr>) -> WriteLock {
+        match origin {
+            Some(addr) => WriteLock::Remote(addr),
+            None => WriteLock::Local,
+        }
+    }
+
+    fn permits(&self, origin: Option<SocketAddr>) -> bool {
+        *self == WriteLock::Shared || *self == WriteLock::holder(origin)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            WriteLock::Shared => String::from("Write lock released: all writers can send input"),
+            WriteLock::Local => String::from("Write lock taken by the local console"),
+            WriteLock::Remote(addr) => format!("Write lock taken by {}", addr),
+        }
+    }
+}
+
+
+// The device output appended to a log file
+pub struct OutputLog {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl OutputLog {
+    pub fn open(path: &str) -> Result<OutputLog, String> {
+        match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Ok(OutputLog { path: String::from(path), writer: BufWriter::new(file) }),
+            Err(e) => Err(format!("Could not open the log file {}: {}", path, e)),
+        }
+    }
+
+    // Flush at the end of each line so the log can be followed while it is written
+    fn write(&mut self, ch: u8) -> std::io::Result<()> {
+        self.writer.write_all(&[ch])?;
+        if ch == b'\n' {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+
+// The state of the device connection as shown to the user
+#[derive(Default)]
+pub struct DeviceState {
+    pub baudrate: AtomicU32,
+    pub connected: AtomicBool,
+    pub received: AtomicU64,
+    pub sent: AtomicU64,
+    log: Mutex<Option<OutputLog>>,
+}
+
+impl DeviceState {
+    pub fn start_log(&self, path: &str) -> Result<(), String> {
+        self.set_log(OutputLog::open(path)?);
+        Ok(())
+    }
+
+    pub fn set_log(&self, log: OutputLog) {
+        self.stop_log();
+        *self.log.lock().unwrap() = Some(log);
+    }
+
+    pub fn stop_log(&self) {
+        if let Some(mut log) = self.log.lock().unwrap().take() {
+            let _ = log.writer.flush();
+        }
+    }
+
+    pub fn log_path(&self) -> Option<String> {
+        self.log.lock().unwrap().as_ref().map(|log| log.path.clone())
+    }
+
+    fn log_output(&self, ch: u8) {
+        let mut log = self.log.lock().unwrap();
+        if let Some(output) = log.as_mut() {
+            if let Err(e) = output.write(ch) {
+                error!("Log Error: {e:?}");
+                *log = None;
+            }
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct ScriptClient {
+    id: u32,
+    tx: Sender<MsgType>,
+    binary: bool,
 }
 
 
 // State for the TermSwitch service
+#[derive(Clone)]
 pub struct TermSwitch {
     switch_tx: Sender<MsgType>,
     console_rx: Receiver<MsgType>,
     serial_rx: Receiver<MsgType>,
     network_rx: Receiver<MsgType>,
-    script_rx: Receiver<MsgType>,
 
-    script_pid: Arc<AtomicU32>,
+    scripts: ScriptTable,
+    script_results: ScriptResults,
     binary_mode: Arc<AtomicBool>,
 
     clients: Arc<AtomicI8>,
+    client_table: ClientTable,
+
+    // The device output goes to the local console when the device is shown
+    visible: Arc<AtomicBool>,
+    unseen: Arc<AtomicBool>,
+
+    state: Arc<DeviceState>,
 
     stop: Arc<AtomicBool>,
 }
@@ -65,22 +232,40 @@ impl TermSwitch {
     pub fn get_network_rx(&self) -> Receiver<MsgType> {
         self.network_rx.clone()
     }
-    pub fn get_script_rx(&self) -> Receiver<MsgType> {
-        self.script_rx.clone()
+    pub fn get_scripts(&self) -> ScriptTable {
+        self.scripts.clone()
     }
-    pub fn get_script_pid(&self) -> Arc::<AtomicU32> {
-        self.script_pid.clone()
-    }
-    pub fn get_binary_mode(&self) -> Arc::<AtomicBool> {
-        self.binary_mode.clone()
+    pub fn get_script_results(&self) -> ScriptResults {
+        self.script_results.clone()
     }
     pub fn get_clients(&self) -> Arc::<AtomicI8> {
         self.clients.clone()
     }
+    pub fn get_client_table(&self) -> ClientTable {
+        self.client_table.clone()
+    }
+    pub fn get_state(&self) -> Arc<DeviceState> {
+        self.state.clone()
+    }
+    pub fn get_binary_mode(&self) -> Arc<AtomicBool> {
+        self.binary_mode.clone()
+    }
+    // Stop sending the device output to the local console
+    pub fn hide(&self) {
+        self.visible.store(false, Ordering::Relaxed);
+    }
+    // Replay the last rows of the recent output to the local console and continue with the live output
+    pub fn show(&self, rows: usize) {
+        self.switch_tx.send(MsgType::Show(rows)).unwrap();
+    }
+    // True if the device had output while it was hidden
+    pub fn has_unseen(&self) -> bool {
+        self.unseen.load(Ordering::Relaxed)
+    }
 }
 
 
-fn net_clients_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType, clients: Arc::<AtomicI8>) {
+fn net_clients_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType, clients: &ClientRegistry) {
     info!("{}: {:?}", prefix, msg);
     let initial_len = net_clients.len();
     net_clients.retain(|elem| {
@@ -94,12 +279,12 @@ fn net_clients_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType
     });
     if net_clients.len() != initial_len {
         info!("{}: {} client(s) removed", prefix, initial_len - net_clients.len());
-        clients.store(net_clients.len().try_into().expect("Get number of clients"), Ordering::Relaxed);
+        clients.update(net_clients);
     }
 }
 
 
-fn net_client_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType, addr: SocketAddr, clients: Arc::<AtomicI8>) {
+fn net_client_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType, addr: SocketAddr, clients: &ClientRegistry) {
     info!("{}: {}", prefix, addr);
     for (pos, elem)  in net_clients.iter().enumerate()  {
         if elem.addr == addr {
@@ -107,13 +292,13 @@ fn net_client_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType,
                 Ok(_) => {
                     info!("{}: Client {} removed at pos: {}", prefix, elem.addr, pos);
                     net_clients.remove(pos);
-                    clients.store(net_clients.len().try_into().expect("Get number of clients"), Ordering::Relaxed);
+                    clients.update(net_clients);
                     return;
                 },
                 Err(_) => {
                     error!("{}: Client {} gone and removed at pos: {}", prefix, elem.addr, pos);
                     net_clients.remove(pos);
-                    clients.store(net_clients.len().try_into().expect("Get number of clients"), Ordering::Relaxed);
+                    clients.update(net_clients);
                     return;
                 }
             }
@@ -122,35 +307,165 @@ fn net_client_send(net_clients: &mut Vec<NetClient>, prefix: &str, msg: MsgType,
 }
 
 
+// Send a line of text to a remote client
+fn net_client_text(client: &NetClient, text: &str) {
+    for ch in format!("\r\n*** {} ***\r\n", text).bytes() {
+        if client.tx.send(MsgType::Console(ch)).is_err() {
+            error!("Text: Client {} gone", client.addr);
+            return;
+        }
+    }
+}
+
+
+// Tell the local console and all remote clients who holds the write lock
+fn write_lock_notify(net_clients: &mut [NetClient], lock: WriteLock, local_warned: &mut bool) {
+    let text = lock.describe();
+    info!("{}", text);
+    println!("\r\n{}\r", text.clone().with(Color::White).on(Color::DarkBlue));
+    for client in net_clients.iter_mut() {
+        client.warned = false;
+        client.info.write_lock.store(lock == WriteLock::Remote(client.addr), Ordering::Relaxed);
+        net_client_text(client, &text);
+    }
+    *local_warned = false;
+}
+
+
+// Remove a client and release the write lock if the client held it
+fn net_client_exit(net_clients: &mut Vec<NetClient>, prefix: &str, addr: SocketAddr, clients: &ClientRegistry,
+                   write_lock: &mut WriteLock, local_warned: &mut bool) {
+    net_client_send(net_clients, prefix, MsgType::Exit, addr, clients);
+    if *write_lock == WriteLock::Remote(addr) {
+        *write_lock = WriteLock::Shared;
+        write_lock_notify(net_clients, *write_lock, local_warned);
+    }
+}
+
+
+// The most recent device output that is replayed to new remote clients
+struct History {
+    data: VecDeque<u8>,
+    size: usize,
+}
+
+impl History {
+    fn new(size: usize) -> History {
+        History { data: VecDeque::with_capacity(size), size }
+    }
+
+    fn push(&mut self, ch: u8) {
+        if self.size == 0 {
+            return;
+        }
+        if self.data.len() == self.size {
+            self.data.pop_front();
+        }
+        self.data.push_back(ch);
+    }
+
+    fn replay(&self) -> Vec<u8> {
+        self.last_lines(usize::MAX)
+    }
+
+    // Start at a line boundary when the oldest output has been dropped so no escape sequence is cut
+    // Output without line breaks (binary data or a long progress line) starts at an escape sequence or a character
+    fn last_lines(&self, rows: usize) -> Vec<u8> {
+        if rows == 0 {
+            return Vec::new();
+        }
+        let data: Vec<u8> = self.data.iter().copied().collect();
+        let breaks: Vec<usize> = data.iter().enumerate().rev().skip(1)
+            .filter(|(_, ch)| **ch == b'\n').map(|(pos, _)| pos).collect();
+        let start = if breaks.len() >= rows {
+            breaks[rows - 1] + 1
+        } else if data.len() < self.size {
+            0
+        } else if let Some(pos) = breaks.last() {
+            pos + 1
+        } else if let Some(pos) = data.iter().position(|&ch| ch == 0x1b) {
+            pos
+        } else {
+            data.iter().position(|&ch| ch & 0xc0 != 0x80).unwrap_or(data.len())
+        };
+        data[start..].to_vec()
+    }
+}
+
+
+// Check if a routing rule applies to the script at a position in the list of running scripts
+fn script_selected(route: ScriptRoute, pos: usize, count: usize) -> bool {
+    match route {
+        ScriptRoute::All => true,
+        ScriptRoute::First => pos == 0,
+        ScriptRoute::Last => pos + 1 == count,
+    }
+}
+
+
+fn script_send(script_clients: &[ScriptClient], id: u32, msg: MsgType) {
+    if let Some(script) = script_clients.iter().find(|elem| elem.id == id) {
+        if script.tx.send(msg).is_err() {
+            error!("Script {} is gone", id);
+        }
+    }
+}
+
+
+// Register a script with the term switch: the reply comes on its own channel so scripts started at the same
+// time from the console and the control socket get their own id
+pub fn register_script(tx: &Sender<MsgType>) -> Result<(u32, Receiver<MsgType>), String> {
+    let (reply_tx, reply_rx) = bounded(1);
+    tx.send(MsgType::ScriptAdd(reply_tx)).map_err(|e| e.to_string())?;
+    match reply_rx.recv() {
+        Ok(MsgType::ScriptAdded(id, rx)) => Ok((id, rx)),
+        Ok(msg) => Err(format!("Unexpected reply: {:?}", msg)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+
 // Start TermSwitch Service
-pub fn start(server: bool) -> TermSwitch {
+pub fn start(server: bool, routing: ScriptRouting, history_size: usize) -> TermSwitch {
     trace!("Starting Terminal Service");
     let (switch_tx, switch_rx) = unbounded();
     let (console_tx, console_rx) = unbounded();
     let (serial_tx, serial_rx) = unbounded();
     let (network_tx, network_rx) = unbounded();
-    let (script_tx, script_rx) = unbounded();
 
     let termswx = TermSwitch {
         switch_tx,
         console_rx,
         serial_rx,
         network_rx,
-        script_rx,
-        script_pid: Arc::new(AtomicU32::new(0)),
+        scripts: Arc::new(Mutex::new(Vec::new())),
+        script_results: Arc::new(Mutex::new(VecDeque::new())),
         binary_mode: Arc::new(AtomicBool::new(false)),
         clients: Arc::new(AtomicI8::new(0)),
+        client_table: Arc::new(Mutex::new(Vec::new())),
+        visible: Arc::new(AtomicBool::new(true)),
+        unseen: Arc::new(AtomicBool::new(false)),
+        state: Arc::new(DeviceState::default()),
         stop: Arc::new(AtomicBool::new(false)),
     };
 
-    let script_pid = termswx.script_pid.clone();
     let binary_mode = termswx.binary_mode.clone();
-    let clients = termswx.clients.clone();
+    let clients = ClientRegistry {
+        count: termswx.clients.clone(),
+        table: termswx.client_table.clone(),
+    };
+    let visible = termswx.visible.clone();
+    let unseen = termswx.unseen.clone();
+    let state = termswx.state.clone();
     let stop = termswx.stop.clone();
 
     // Exchange messages
     thread::spawn(move || {
         let mut net_clients: Vec<NetClient> = Vec::new();
+        let mut script_clients: Vec<ScriptClient> = Vec::new();
+        let mut write_lock = WriteLock::Shared;
+        let mut local_warned = false;
+        let mut history = History::new(history_size);
 
         loop {
             if stop.load(Ordering::Relaxed) {
@@ -158,20 +473,73 @@ pub fn start(server: bool) -> TermSwitch {
             }
             // We need to decide the origin
             match switch_rx.recv() {
-                Ok(MsgType::Add(addr)) => {
+                Ok(MsgType::Add(addr, role)) => {
                     let (tx, rx) = unbounded();
                     net_clients.push(NetClient {
                         addr: addr.clone(),
                         tx,
+                        info: Arc::new(ClientInfo {
+                            addr,
+                            role,
+                            connected: chrono::offset::Local::now(),
+                            start: Instant::now(),
+                            bytes_in: AtomicU64::new(0),
+                            bytes_out: AtomicU64::new(0),
+                            write_lock: AtomicBool::new(false),
+                        }),
+                        warned: false,
                     });
 
-                    info!("Add: {}", addr);
-                    clients.store(net_clients.len().try_into().expect("Get number of clients"), Ordering::Relaxed);
+                    info!("Add: {} as {:?}", addr, role);
+                    let client = net_clients.last().unwrap();
+                    for ch in history.replay() {
+                        let _ = client.tx.send(MsgType::Console(ch));
+                        client.info.bytes_out.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if write_lock != WriteLock::Shared {
+                        net_client_text(net_clients.last().unwrap(), &write_lock.describe());
+                    }
+                    clients.update(&net_clients);
                     network_tx.send(MsgType::Added(rx)).unwrap();
                 }
                 Ok(MsgType::Added(_)) => (),
                 Ok(MsgType::Console(ch)) => {
                     trace!("console: {:#02x} '{}'", ch, ch as char);
+                    if !write_lock.permits(None) {
+                        if !local_warned {
+                            let text = format!("Input ignored: {}", write_lock.describe());
+                            println!("\r\n{}\r", text.with(Color::White).on(Color::DarkBlue));
+                            local_warned = true;
+                        }
+                        continue;
+                    }
+                    state.sent.fetch_add(1, Ordering::Relaxed);
+                    match serial_tx.send(MsgType::Serial(ch)) {
+                        Ok(_) => (),
+                        Err(_) => {
+                            error!("Client connection is dead");
+                        }
+                    }
+                }
+                Ok(MsgType::NetInput(addr, ch)) => {
+                    trace!("network {}: {:#02x} '{}'", addr, ch, ch as char);
+                    let Some(client) = net_clients.iter_mut().find(|elem| elem.addr == addr) else {
+                        continue;
+                    };
+                    client.info.bytes_in.fetch_add(1, Ordering::Relaxed);
+                    if client.info.role == ClientRole::Observer || !write_lock.permits(Some(addr)) {
+                        if !client.warned {
+                            client.warned = true;
+                            let text = if client.info.role == ClientRole::Observer {
+                                String::from("Input ignored: observers cannot send input")
+                            } else {
+                                format!("Input ignored: {}", write_lock.describe())
+                            };
+                            net_client_text(client, &text);
+                        }
+                        continue;
+                    }
+                    state.sent.fetch_add(1, Ordering::Relaxed);
                     match serial_tx.send(MsgType::Serial(ch)) {
                         Ok(_) => (),
                         Err(_) => {
@@ -179,6 +547,25 @@ pub fn start(server: bool) -> TermSwitch {
                         }
                     }
                 }
+                Ok(MsgType::TakeControl(origin)) => {
+                    if let Some(addr) = origin {
+                        match net_clients.iter().find(|elem| elem.addr == addr) {
+                            Some(client) if client.info.role == ClientRole::Writer => (),
+                            Some(client) => {
+                                net_client_text(client, "Observers cannot take the write lock");
+                                continue;
+                            }
+                            None => continue,
+                        }
+                    }
+                    // Taking the lock again releases it
+                    write_lock = if write_lock == WriteLock::holder(origin) {
+                        WriteLock::Shared
+                    } else {
+                        WriteLock::holder(origin)
+                    };
+                    write_lock_notify(&mut net_clients, write_lock, &mut local_warned);
+                }
                 Ok(MsgType::SerialClose) => {
                     trace!("serial close");
                     serial_tx.send(MsgType::SerialClose).unwrap();
@@ -187,39 +574,125 @@ pub fn start(server: bool) -> TermSwitch {
                     trace!("serial break");
                     serial_tx.send(MsgType::SerialBreak).unwrap();
                 }
+                Ok(MsgType::SerialBaudrate(rate)) => {
+                    trace!("serial baudrate {}", rate);
+                    serial_tx.send(MsgType::SerialBaudrate(rate)).unwrap();
+                }
                 Ok(MsgType::Serial(ch)) => {
                     trace!("serial: {:#02x} '{}'", ch, ch as char);
+                    history.push(ch);
+                    state.received.fetch_add(1, Ordering::Relaxed);
+                    state.log_output(ch);
+                    // The output is only counted as unseen when it would have been shown
+                    let shown = visible.load(Ordering::Relaxed);
                     if server {
-                        net_clients_send(&mut net_clients, "Serial", MsgType::Console(ch), clients.clone());
+                        net_clients_send(&mut net_clients, "Serial", MsgType::Console(ch), &clients);
+                        for client in net_clients.iter() {
+                            client.info.bytes_out.fetch_add(1, Ordering::Relaxed);
+                        }
                     }
-                    if script_pid.load(Ordering::Relaxed) != 0 {
-                        script_tx.send(MsgType::Console(ch)).unwrap();
-                        if !binary_mode.load(Ordering::Relaxed) {
+                    if !script_clients.is_empty() {
+                        let binary = binary_mode.load(Ordering::Relaxed);
+                        let count = script_clients.len();
+                        for (pos, script) in script_clients.iter().enumerate() {
+                            // A file transfer in binary mode gets the device output for itself
+                            let selected = if binary {
+                                script.binary
+                            } else {
+                                script_selected(routing.output, pos, count)
+                            };
+                            if selected && script.tx.send(MsgType::Console(ch)).is_err() {
+                                error!("Script {} is gone", script.id);
+                            }
+                        }
+                        if !binary && shown {
                             console_tx.send(MsgType::Console(ch)).unwrap();
+                        } else if !binary {
+                            unseen.store(true, Ordering::Relaxed);
                         }
+                    } else if shown {
+                        console_tx.send(MsgType::Console(ch)).unwrap();
                     } else {
+                        unseen.store(true, Ordering::Relaxed);
+                    }
+                }
+                Ok(MsgType::Show(rows)) => {
+                    // Done here so no output is lost or repeated between the history and the live output
+                    visible.store(true, Ordering::Relaxed);
+                    unseen.store(false, Ordering::Relaxed);
+                    for ch in history.last_lines(rows) {
                         console_tx.send(MsgType::Console(ch)).unwrap();
                     }
                 }
+                Ok(MsgType::History(tx)) => {
+                    let _ = tx.send(history.replay());
+                }
                 Ok(MsgType::NetClientExit(addr)) => {
-                    net_client_send(&mut net_clients, "NetClientExit", MsgType::Exit, addr, clients.clone());
+                    net_client_exit(&mut net_clients, "NetClientExit", addr, &clients, &mut write_lock, &mut local_warned);
+                }
+                Ok(MsgType::NetClientKick(addr)) => {
+                    if let Some(client) = net_clients.iter().find(|elem| elem.addr == addr) {
+                        net_client_text(client, "Disconnected by the server");
+                    }
+                    net_client_exit(&mut net_clients, "NetClientKick", addr, &clients, &mut write_lock, &mut local_warned);
                 }
                 Ok(MsgType::Exit) => {
                     info!("exit");
                     if server {
-                        net_clients_send(&mut net_clients, "Exit", MsgType::Exit, clients.clone());
+                        net_clients_send(&mut net_clients, "Exit", MsgType::Exit, &clients);
                     } else {
                         info!("Send exit to console service");
                         console_tx.send(MsgType::Exit).unwrap();
                     }
                 }
-                Ok(MsgType::ScriptAlertResponse(ch)) => {
-                    trace!("script alert response: {ch:#02x}");
-                    script_tx.send(MsgType::ScriptAlertResponse(ch)).unwrap();
+                Ok(MsgType::ScriptAdd(reply_tx)) => {
+                    let id = NEXT_SCRIPT_ID.fetch_add(1, Ordering::Relaxed);
+                    let (tx, rx) = unbounded();
+                    script_clients.push(ScriptClient {
+                        id,
+                        tx,
+                        binary: false,
+                    });
+                    info!("Add script: {}", id);
+                    let _ = reply_tx.send(MsgType::ScriptAdded(id, rx));
+                }
+                Ok(MsgType::ScriptAdded(..)) => (),
+                Ok(MsgType::ScriptOutput(id, ch)) => {
+                    let binary = binary_mode.load(Ordering::Relaxed);
+                    let count = script_clients.len();
+                    // Other scripts must not disturb a file transfer in binary mode
+                    let allowed = |pos: usize| if binary {
+                        script_clients[pos].binary
+                    } else {
+                        script_selected(routing.write, pos, count)
+                    };
+                    match script_clients.iter().position(|elem| elem.id == id) {
+                        Some(pos) if allowed(pos) => {
+                            trace!("script {}: {:#02x} '{}'", id, ch, ch as char);
+                            state.sent.fetch_add(1, Ordering::Relaxed);
+                            serial_tx.send(MsgType::Serial(ch)).unwrap();
+                        }
+                        _ => {
+                            trace!("script {}: no write access for {:#02x}", id, ch);
+                        }
+                    }
+                }
+                Ok(MsgType::ScriptBinary(id, on)) => {
+                    info!("script {}: binary mode {}", id, on);
+                    if let Some(script) = script_clients.iter_mut().find(|elem| elem.id == id) {
+                        script.binary = on;
+                    }
+                    binary_mode.store(script_clients.iter().any(|elem| elem.binary), Ordering::Relaxed);
+                }
+                Ok(MsgType::ScriptAlertResponse(id, ch)) => {
+                    trace!("script {} alert response: {ch:#02x}", id);
+                    script_send(&script_clients, id, MsgType::ScriptAlertResponse(id, ch));
                 }
-                Ok(MsgType::ScriptDone) => {
-                    info!("Send done to script client");
-                    script_tx.send(MsgType::ScriptDone).unwrap();
+                Ok(MsgType::ScriptDone(id)) => {
+                    info!("Send done to script client {}", id);
+                    script_send(&script_clients, id, MsgType::ScriptDone(id));
+                    script_clients.retain(|elem| elem.id != id);
+                    binary_mode.store(script_clients.iter().any(|elem| elem.binary), Ordering::Relaxed);
                 }
                 Err(e) => {
                     error!("Error: {e:?}");
@@ -230,3 +703,80 @@ pub fn start(server: bool) -> TermSwitch {
     });
     termswx
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn history() {
+        let mut history = History::new(16);
+        for ch in b"dev# ls\r\n".iter() {
+            history.push(*ch);
+        }
+        assert_eq!(history.replay(), b"dev# ls\r\n");
+        for ch in b"bin etc\r\ndev# ".iter() {
+            history.push(*ch);
+        }
+        assert_eq!(history.replay(), b"bin etc\r\ndev# ");
+        assert_eq!(history.last_lines(1), b"dev# ");
+        assert_eq!(history.last_lines(2), b"bin etc\r\ndev# ");
+        assert_eq!(history.last_lines(0), b"");
+        // A long line without line breaks starts at the first escape sequence or at a whole character
+        for ch in b"01\x1b[1m56789abcdef".iter() {
+            history.push(*ch);
+        }
+        assert_eq!(history.replay(), b"\x1b[1m56789abcdef");
+        for ch in "\u{2588}".repeat(6).as_bytes() {
+            history.push(*ch);
+        }
+        assert_eq!(history.replay(), "\u{2588}".repeat(5).as_bytes());
+        let mut history = History::new(0);
+        history.push(b'x');
+        assert_eq!(history.replay(), b"");
+    }
+
+    #[test]
+    fn concurrent_scripts() {
+        let routing = ScriptRouting { output: ScriptRoute::All, write: ScriptRoute::All };
+        let termswx = start(false, routing, 0);
+        let starters: Vec<_> = (0..2).map(|_| {
+            let tx = termswx.get_switch_tx();
+            thread::spawn(move || {
+                let mut ids = Vec::new();
+                for _ in 0..50 {
+                    let (id, rx) = register_script(&tx).unwrap();
+                    // The channel must belong to the id that was handed out with it
+                    tx.send(MsgType::ScriptAlertResponse(id, b'y')).unwrap();
+                    assert!(matches!(rx.recv_timeout(Duration::from_secs(1)),
+                                     Ok(MsgType::ScriptAlertResponse(got, b'y')) if got == id));
+                    ids.push(id);
+                }
+                ids
+            })
+        }).collect();
+        let mut ids: Vec<u32> = starters.into_iter().flat_map(|thr| thr.join().unwrap()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn scripts_on_two_devices() {
+        let routing = ScriptRouting { output: ScriptRoute::All, write: ScriptRoute::All };
+        let first = start(false, routing, 0);
+        let second = start(false, routing, 0);
+        let (first_id, first_rx) = register_script(&first.get_switch_tx()).unwrap();
+        let (second_id, second_rx) = register_script(&second.get_switch_tx()).unwrap();
+        assert_ne!(first_id, second_id);
+        // A prompt response reaches only the script that has the id
+        second.get_switch_tx().send(MsgType::ScriptAlertResponse(second_id, b'2')).unwrap();
+        first.get_switch_tx().send(MsgType::ScriptAlertResponse(second_id, b'1')).unwrap();
+        assert!(matches!(second_rx.recv_timeout(Duration::from_secs(1)),
+                         Ok(MsgType::ScriptAlertResponse(id, b'2')) if id == second_id));
+        assert!(first_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        assert!(second_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
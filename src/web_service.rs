@@ -0,0 +1,545 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 19:41
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// Web terminal: serves a page with xterm.js and bridges its WebSocket to the term switch
+// The browser is handled like a remote TCP client and shares the maxclients limit with the TCP server
+
+use crate::network_service::{Server, NetStream, ClientStream, Handshake, authenticate, begin_handshake,
+                             bind_listener, client_input, register_client, reject_client};
+use crate::term_switch::MsgType;
+
+use log::{error, trace};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel::Receiver;
+use crossterm::style::{Color, Stylize};
+use tungstenite::{Message, WebSocket, protocol::Role, handshake::derive_accept_key};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_MAX_SIZE: usize = 8192;
+// Page and asset requests do not count against maxclients but the open requests are limited
+const MAX_OPEN_REQUESTS: usize = 16;
+// How long the client thread waits for browser input before it forwards the device output
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// The xterm.js files from the CDN, or from the assets folder of the configuration
+const CDN_ASSETS: [&str; 3] = [
+    "https://cdn.jsdelivr.net/npm/@xterm/xterm@5.5.0/css/xterm.min.css",
+    "https://cdn.jsdelivr.net/npm/@xterm/xterm@5.5.0/lib/xterm.min.js",
+    "https://cdn.jsdelivr.net/npm/@xterm/addon-fit@0.10.0/lib/addon-fit.min.js",
+];
+const ASSET_FILES: [&str; 3] = ["xterm.min.css", "xterm.min.js", "addon-fit.min.js"];
+
+// Without xterm.js (no access to the CDN) the page falls back to a basic terminal that shows the text without
+// colors and cursor positioning
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>TermSWX - @TITLE@</title>
+<link rel="stylesheet" href="@CSS@">
+<script src="@XTERM@"></script>
+<script src="@FIT@"></script>
+<style>
+html, body, #terminal { height: 100%; margin: 0; background: #000; }
+pre.basic { margin: 0; padding: 2px; color: #ccc; font: 15px monospace; white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<div id="terminal"></div>
+<script>
+function basicTerminal(element) {
+  const screen = document.createElement("pre");
+  screen.className = "basic";
+  element.appendChild(screen);
+  const decoder = new TextDecoder();
+  const lines = [""];
+  let col = 0;
+  let escape = 0;
+  return {
+    write(data) {
+      const text = typeof data === "string" ? data : decoder.decode(data, { stream: true });
+      for (const ch of text) {
+        if (escape === 1) {
+          escape = ch === "[" ? 2 : ch === "]" ? 3 : 0;
+        } else if (escape === 2) {
+          escape = ch >= "@" && ch <= "~" ? 0 : 2;
+        } else if (escape === 3) {
+          escape = ch === "\x07" ? 0 : ch === "\x1b" ? 1 : 3;
+        } else if (ch === "\x1b") {
+          escape = 1;
+        } else if (ch === "\r") {
+          col = 0;
+        } else if (ch === "\n") {
+          lines.push("");
+          col = 0;
+          if (lines.length > 10000) {
+            lines.shift();
+          }
+        } else if (ch === "\b") {
+          col = Math.max(col - 1, 0);
+        } else if (ch >= " ") {
+          const line = lines[lines.length - 1];
+          lines[lines.length - 1] = line.slice(0, col).padEnd(col) + ch + line.slice(col + 1);
+          col++;
+        }
+      }
+      screen.textContent = lines.join("\n");
+      window.scrollTo(0, document.body.scrollHeight);
+    },
+    onData(callback) {
+      const keys = { Enter: "\r", Backspace: "\x7f", Tab: "\t", Escape: "\x1b", Delete: "\x1b[3~",
+                     ArrowUp: "\x1b[A", ArrowDown: "\x1b[B", ArrowRight: "\x1b[C", ArrowLeft: "\x1b[D",
+                     Home: "\x1b[H", End: "\x1b[F" };
+      document.addEventListener("keydown", (event) => {
+        let data = keys[event.key];
+        if (event.ctrlKey && event.key.length === 1) {
+          const code = event.key.toUpperCase().charCodeAt(0);
+          data = code >= 64 && code < 96 ? String.fromCharCode(code - 64) : undefined;
+        } else if (!data && !event.altKey && !event.metaKey && event.key.length === 1) {
+          data = event.key;
+        }
+        if (data) {
+          event.preventDefault();
+          callback(data);
+        }
+      });
+      document.addEventListener("paste", (event) => callback(event.clipboardData.getData("text")));
+    },
+    focus() {},
+  };
+}
+
+let term;
+if (typeof Terminal === "undefined") {
+  term = basicTerminal(document.getElementById("terminal"));
+} else {
+  term = new Terminal({ scrollback: 10000 });
+  const fit = new FitAddon.FitAddon();
+  term.loadAddon(fit);
+  term.open(document.getElementById("terminal"));
+  fit.fit();
+  window.addEventListener("resize", () => fit.fit());
+}
+const scheme = location.protocol === "https:" ? "wss:" : "ws:";
+const socket = new WebSocket(scheme + "//" + location.host + "/ws");
+socket.binaryType = "arraybuffer";
+const encoder = new TextEncoder();
+socket.onmessage = (event) => term.write(new Uint8Array(event.data));
+socket.onclose = () => term.write("\r\n*** Connection closed ***\r\n");
+term.onData((data) => socket.send(encoder.encode(data)));
+term.focus();
+</script>
+</body>
+</html>
+"#;
+
+
+fn page(title: &str, assets: &str) -> String {
+    let urls: Vec<String> = if assets.is_empty() {
+        CDN_ASSETS.iter().map(|url| url.to_string()).collect()
+    } else {
+        ASSET_FILES.iter().map(|name| format!("/assets/{}", name)).collect()
+    };
+    PAGE.replace("@TITLE@", &escape_html(title))
+        .replace("@CSS@", &urls[0])
+        .replace("@XTERM@", &urls[1])
+        .replace("@FIT@", &urls[2])
+}
+
+
+// Only the xterm.js files are served from the assets folder
+fn respond_asset(stream: &mut NetStream, assets: &str, path: &str) {
+    let name = path.trim_start_matches("/assets/");
+    let content = if ASSET_FILES.contains(&name) && !assets.is_empty() {
+        std::fs::read_to_string(Path::new(assets).join(name)).ok()
+    } else {
+        None
+    };
+    match content {
+        Some(body) if name.ends_with(".css") => respond(stream, "200 OK", "text/css; charset=utf-8", &body),
+        Some(body) => respond(stream, "200 OK", "text/javascript; charset=utf-8", &body),
+        None => respond(stream, "404 Not Found", "text/plain", "Not found\n"),
+    }
+}
+
+
+// The request line and the headers of an HTTP request, header names in lower case
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> &str {
+        self.headers.get(name).map_or("", |value| value.as_str())
+    }
+
+    fn is_websocket(&self) -> bool {
+        self.header("upgrade").eq_ignore_ascii_case("websocket") && !self.header("sec-websocket-key").is_empty()
+    }
+
+    // A browser sends the origin of the page so a page from another site cannot use the terminal
+    // Clients that are not browsers do not send it
+    fn same_origin(&self) -> bool {
+        let origin = self.header("origin");
+        if origin.is_empty() {
+            return true;
+        }
+        let authority = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+        authority.is_some_and(|authority| authority.eq_ignore_ascii_case(self.header("host")))
+    }
+}
+
+
+fn parse_request(text: &str) -> Option<Request> {
+    let mut lines = text.split("\r\n");
+    let mut parts = lines.next()?.split(' ');
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut headers = HashMap::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+    Some(Request { method, path, headers })
+}
+
+
+// Read the request head without reading into the WebSocket frames that may follow it
+fn read_request(stream: &mut NetStream) -> Result<Request, String> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            Ok(_) => return Err(String::from("Connection closed")),
+            Err(e) => return Err(format!("Request error: {}", e)),
+        }
+        if head.len() > REQUEST_MAX_SIZE {
+            return Err(String::from("Request too large"));
+        }
+    }
+    parse_request(&String::from_utf8_lossy(&head)).ok_or(String::from("Invalid request"))
+}
+
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+fn respond(stream: &mut NetStream, status: &str, content_type: &str, body: &str) {
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, content_type, body.len(), body);
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.flush();
+}
+
+
+// The WebSocket messages from the browser as a byte stream
+struct WebStream {
+    socket: WebSocket<NetStream>,
+    input: VecDeque<u8>,
+}
+
+impl Read for WebStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.input.is_empty() {
+            match self.socket.read() {
+                Ok(msg @ (Message::Text(_) | Message::Binary(_))) => self.input.extend(msg.into_data()),
+                Ok(Message::Close(_)) => return Ok(0),
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(e)) => return Err(e),
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        let cnt = buf.len().min(self.input.len());
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..cnt)) {
+            *dst = src;
+        }
+        Ok(cnt)
+    }
+}
+
+impl Write for WebStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send(Message::binary(buf.to_vec())).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ClientStream for WebStream {
+    fn set_timeout(&self, timeout: Option<Duration>) {
+        self.socket.get_ref().set_timeout(timeout);
+    }
+}
+
+
+fn show_closed(addr: SocketAddr, reason: &str) {
+    let now = chrono::offset::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let text = format!("Web client connection closed at {} from {}: {}", now, addr, reason);
+    trace!("{}", text);
+    println!("\r{}\r", text.with(Color::White).on(Color::Black));
+}
+
+
+// Exchange the device output and the browser input until one of the sides closes
+fn serve_browser(server: &Server, addr: SocketAddr, client_rx: Receiver<MsgType>, mut stream: WebStream) {
+    stream.set_timeout(Some(POLL_INTERVAL));
+    let mut buffer = [0; 256];
+    loop {
+        let mut output = Vec::new();
+        let mut exit = false;
+        for msg in client_rx.try_iter() {
+            match msg {
+                MsgType::Console(val) => output.push(val),
+                MsgType::Exit => {
+                    exit = true;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        if !output.is_empty() && stream.write_all(&output).is_err() {
+            show_closed(addr, "Send error");
+            server.switch_tx.send(MsgType::NetClientExit(addr)).unwrap();
+            return;
+        }
+        if exit {
+            trace!("Web Exit received");
+            let _ = stream.socket.close(None);
+            let _ = stream.socket.flush();
+            return;
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) => {
+                show_closed(addr, "Closed by the browser");
+                server.switch_tx.send(MsgType::NetClientExit(addr)).unwrap();
+                return;
+            }
+            Ok(cnt) => {
+                for msg in client_input(addr, &buffer[..cnt], &server.control) {
+                    trace!("web input: {:?} from {}", msg, addr);
+                    server.switch_tx.send(msg).unwrap();
+                }
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+            Err(e) => {
+                show_closed(addr, &format!("Receive error: {}", e));
+                server.switch_tx.send(MsgType::NetClientExit(addr)).unwrap();
+                return;
+            }
+        }
+    }
+}
+
+
+// Upgrade the connection to a WebSocket and hand the browser over to the term switch
+fn add_browser(server: &Server, addr: SocketAddr, mut stream: NetStream, request: &Request, handshake: Handshake) {
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                            Sec-WebSocket-Accept: {}\r\n\r\n",
+                           derive_accept_key(request.header("sec-websocket-key").as_bytes()));
+    if stream.write_all(response.as_bytes()).is_err() {
+        return;
+    }
+    let mut stream = WebStream {
+        socket: WebSocket::from_raw_socket(stream, Role::Server, None),
+        input: VecDeque::new(),
+    };
+    let pending = match authenticate(&mut stream, addr, server.access.password()) {
+        Ok(pending) => pending,
+        Err(e) => {
+            reject_client(addr, &e);
+            let _ = stream.socket.close(None);
+            return;
+        }
+    };
+    let client_rx = register_client(server, addr);
+    drop(handshake);
+    let Some(client_rx) = client_rx else {
+        let _ = stream.write_all(b"\r\n*** Maximum number of clients connected ***\r\n");
+        let _ = stream.socket.close(None);
+        return;
+    };
+    if let Some(val) = pending {
+        let _ = server.switch_tx.send(MsgType::NetInput(addr, val));
+    }
+    serve_browser(server, addr, client_rx, stream);
+}
+
+
+// An accepted connection that has not become a browser client yet
+struct OpenRequest {
+    count: Arc<AtomicUsize>,
+}
+
+impl OpenRequest {
+    fn begin(count: &Arc<AtomicUsize>) -> Option<OpenRequest> {
+        if count.fetch_add(1, Ordering::Relaxed) >= MAX_OPEN_REQUESTS {
+            count.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(OpenRequest { count: count.clone() })
+    }
+}
+
+impl Drop for OpenRequest {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+
+fn handle_connection(server: Server, addr: SocketAddr, socket: TcpStream, title: &str, assets: &str,
+                     open: OpenRequest) {
+    let _ = socket.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let mut stream = match NetStream::accept(&server, socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            reject_client(addr, &e);
+            return;
+        }
+    };
+    stream.set_timeout(Some(REQUEST_TIMEOUT));
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            trace!("Web request from {}: {}", addr, e);
+            return;
+        }
+    };
+    trace!("Web request from {}: {} {}", addr, request.method, request.path);
+    stream.set_timeout(None);
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/ws") if request.is_websocket() && !request.same_origin() => {
+            reject_client(addr, &format!("Page from {} is not served by {}", request.header("origin"),
+                                         request.header("host")));
+            respond(&mut stream, "403 Forbidden", "text/plain", "Forbidden\n");
+        }
+        ("GET", "/ws") if request.is_websocket() => {
+            drop(open);
+            // Only the terminal connection takes a client slot
+            match begin_handshake(&server, addr) {
+                Some(handshake) => add_browser(&server, addr, stream, &request, handshake),
+                None => respond(&mut stream, "503 Service Unavailable", "text/plain",
+                                "Maximum number of clients connected\n"),
+            }
+        }
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", &page(title, assets)),
+        ("GET", path) if path.starts_with("/assets/") => respond_asset(&mut stream, assets, path),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n"),
+    }
+}
+
+
+// Start the web terminal with a listener on each address
+pub fn start_web_server(server: &Server, addrs: &[SocketAddr], title: String, assets: String, start: Instant) {
+    for addr in addrs.iter().copied() {
+        trace!("start_web_server address: {}", addr);
+        let server = server.clone();
+        let title = title.clone();
+        let assets = assets.clone();
+        let open = Arc::new(AtomicUsize::new(0));
+
+        thread::spawn(move || {
+            let listener = match bind_listener(addr) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Error: {:?}", e);
+                    crate::terminate(start, &format!("Could not listen on {}: {}", addr, e));
+                    return;
+                }
+            };
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(socket) => {
+                        let addr = socket.peer_addr().unwrap();
+                        if !server.access.permits(&addr.ip()) {
+                            reject_client(addr, "Address not allowed");
+                            continue;
+                        }
+                        let Some(request) = OpenRequest::begin(&open) else {
+                            trace!("Web request from {} dropped: {} requests open", addr, MAX_OPEN_REQUESTS);
+                            continue;
+                        };
+                        let server = server.clone();
+                        let title = title.clone();
+                        let assets = assets.clone();
+                        thread::spawn(move || handle_connection(server, addr, socket, &title, &assets, request));
+                    }
+                    Err(e) => {
+                        error!("Web connection error: {:?}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request() {
+        let request = parse_request("GET /ws HTTP/1.1\r\nHost: orion:8080\r\nUpgrade: WebSocket\r\n\
+                                     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/ws");
+        assert_eq!(request.header("host"), "orion:8080");
+        assert!(request.is_websocket());
+        let request = parse_request("GET / HTTP/1.1\r\nHost: orion:8080\r\n\r\n").unwrap();
+        assert!(!request.is_websocket());
+        assert_eq!(parse_request("GET / HTTP/1.1\r\nNo header line\r\n\r\n"), None);
+        assert_eq!(parse_request(""), None);
+    }
+
+    #[test]
+    fn origin() {
+        let upgrade = |origin: &str| parse_request(&format!("GET /ws HTTP/1.1\r\nHost: orion:8080\r\n{}\
+                                                            Upgrade: websocket\r\n\r\n", origin)).unwrap();
+        assert!(upgrade("Origin: http://orion:8080\r\n").same_origin());
+        assert!(upgrade("Origin: https://ORION:8080\r\n").same_origin());
+        assert!(upgrade("").same_origin());
+        assert!(!upgrade("Origin: http://evil.example.com\r\n").same_origin());
+        assert!(!upgrade("Origin: http://orion:8081\r\n").same_origin());
+        assert!(!upgrade("Origin: null\r\n").same_origin());
+    }
+
+    #[test]
+    fn open_requests() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let open: Vec<OpenRequest> = (0..MAX_OPEN_REQUESTS).filter_map(|_| OpenRequest::begin(&count)).collect();
+        assert_eq!(open.len(), MAX_OPEN_REQUESTS);
+        assert!(OpenRequest::begin(&count).is_none());
+        drop(open);
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+        assert!(OpenRequest::begin(&count).is_some());
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(escape_html("/dev/ttyUSB0"), "/dev/ttyUSB0");
+        assert_eq!(escape_html("<b>&\"</b>"), "&lt;b&gt;&amp;&quot;&lt;/b&gt;");
+        let html = page("<board>", "");
+        assert!(html.contains("TermSWX - &lt;board&gt;") && html.contains(CDN_ASSETS[1]));
+        let html = page("board", "/opt/xterm");
+        assert!(html.contains("<script src=\"/assets/xterm.min.js\">") && !html.contains("cdn.jsdelivr.net"));
+    }
+}
@@ -0,0 +1,494 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 18:08
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// XMODEM (128 byte blocks with checksum or CRC and 1K blocks) and YMODEM batch file transfer
+// The receiver asks for CRC and falls back to checksum when the sender does not answer, the sender handles both.
+
+use crate::file_transfer::{TransferPort, FileData};
+
+use log::{info, trace};
+use std::time::{Duration, Instant};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const BS: u8 = 0x08;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const CRC: u8 = b'C';
+
+const RETRIES: usize = 10;
+// Number of requests sent every START_INTERVAL before giving up on the sender
+const START_REQUESTS: usize = 20;
+// Number of CRC requests before an XMODEM receiver asks for checksum instead
+const CRC_REQUESTS: usize = 3;
+#[cfg(not(test))]
+const START_INTERVAL: Duration = Duration::from_secs(3);
+#[cfg(test)]
+const START_INTERVAL: Duration = Duration::from_millis(300);
+// Time allowed for the receiver to ask for the first block
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const CHAR_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub const CANCELLED: &str = "Cancelled by the user";
+
+
+// CRC-16/XMODEM (also used by ZMODEM)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for val in data {
+        crc ^= u16::from(*val) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, val| sum.wrapping_add(*val))
+}
+
+
+// Tell the other side that the transfer has been cancelled
+pub fn cancel(port: &mut dyn TransferPort) {
+    info!("Cancel transfer");
+    port.write(&[CAN; 8]);
+    port.write(&[BS; 10]);
+}
+
+
+// Wait for the receiver to ask for a block: true if it wants CRC instead of checksum
+fn wait_start(port: &mut dyn TransferPort) -> Result<bool, String> {
+    let start = Instant::now();
+    let mut cancels = 0;
+    while start.elapsed() < START_TIMEOUT {
+        match port.read(CHAR_TIMEOUT) {
+            Some(CRC) => return Ok(true),
+            Some(NAK) => return Ok(false),
+            Some(CAN) => {
+                cancels += 1;
+                if cancels >= 2 {
+                    return Err(String::from("Cancelled by the receiver"));
+                }
+            }
+            Some(_) => cancels = 0,
+            None => {
+                if port.cancelled() {
+                    cancel(port);
+                    return Err(String::from(CANCELLED));
+                }
+            }
+        }
+    }
+    Err(String::from("The receiver did not start"))
+}
+
+
+// Wait for the receiver to acknowledge a block: a timeout is handled as a NAK
+fn wait_response(port: &mut dyn TransferPort) -> Result<u8, String> {
+    let start = Instant::now();
+    let mut cancels = 0;
+    while start.elapsed() < BLOCK_TIMEOUT {
+        match port.read(CHAR_TIMEOUT) {
+            Some(ACK) => return Ok(ACK),
+            Some(NAK) => return Ok(NAK),
+            Some(CAN) => {
+                cancels += 1;
+                if cancels >= 2 {
+                    return Err(String::from("Cancelled by the receiver"));
+                }
+            }
+            Some(_) => (),
+            None => {
+                if port.cancelled() {
+                    cancel(port);
+                    return Err(String::from(CANCELLED));
+                }
+            }
+        }
+    }
+    Ok(NAK)
+}
+
+
+// Send a block padded to the block size and retry until it is acknowledged
+fn send_block(port: &mut dyn TransferPort, num: u8, data: &[u8], size: usize, crc: bool, pad: u8)
+              -> Result<(), String> {
+    let mut packet = vec![if size == 1024 { STX } else { SOH }, num, !num];
+    packet.extend_from_slice(data);
+    packet.resize(3 + size, pad);
+    if crc {
+        let value = crc16(&packet[3..]);
+        packet.extend_from_slice(&value.to_be_bytes());
+    } else {
+        let value = checksum(&packet[3..]);
+        packet.push(value);
+    }
+    for retry in 0..RETRIES {
+        trace!("Send block {} size {} retry {}", num, size, retry);
+        port.write(&packet);
+        if wait_response(port)? == ACK {
+            return Ok(());
+        }
+    }
+    cancel(port);
+    Err(format!("Block {} was not acknowledged", num))
+}
+
+
+fn send_eot(port: &mut dyn TransferPort) -> Result<(), String> {
+    for _ in 0..RETRIES {
+        port.write(&[EOT]);
+        if wait_response(port)? == ACK {
+            return Ok(());
+        }
+    }
+    Err(String::from("End of transmission was not acknowledged"))
+}
+
+
+// Send the file content in blocks numbered from 1 followed by the end of transmission
+fn send_data(port: &mut dyn TransferPort, name: &str, data: &[u8], crc: bool, large: bool) -> Result<(), String> {
+    let total = data.len() as u64;
+    let mut num: u8 = 1;
+    let mut pos = 0;
+    port.progress(name, 0, Some(total));
+    while pos < data.len() {
+        // 1K blocks need CRC and a short remainder is sent in a small block
+        let size = if large && crc && data.len() - pos > 128 { 1024 } else { 128 };
+        let end = (pos + size).min(data.len());
+        send_block(port, num, &data[pos..end], size, crc, SUB)?;
+        pos = end;
+        num = num.wrapping_add(1);
+        port.progress(name, pos as u64, Some(total));
+    }
+    send_eot(port)
+}
+
+
+// Send a file with XMODEM: the receiver decides between checksum and CRC
+pub fn send(port: &mut dyn TransferPort, file: &FileData, large: bool) -> Result<(), String> {
+    let crc = wait_start(port)?;
+    send_data(port, &file.name, &file.data, crc, large)
+}
+
+
+// Send files with YMODEM: block 0 holds the filename and size and an empty block 0 ends the batch
+pub fn send_batch(port: &mut dyn TransferPort, files: &[FileData]) -> Result<(), String> {
+    for file in files {
+        let crc = wait_start(port)?;
+        let mut header = file.name.as_bytes().to_vec();
+        header.push(0);
+        header.extend_from_slice(file.data.len().to_string().as_bytes());
+        let size = if header.len() > 128 { 1024 } else { 128 };
+        send_block(port, 0, &header, size, crc, 0)?;
+        let crc = wait_start(port)?;
+        send_data(port, &file.name, &file.data, crc, true)?;
+    }
+    let crc = wait_start(port)?;
+    send_block(port, 0, &[], 128, crc, 0)
+}
+
+
+enum Packet {
+    Block(u8, Vec<u8>),
+    Eot,
+    Cancel,
+    Timeout,
+    Bad,
+}
+
+
+// Read the next block: bytes before the start of a block are ignored
+fn read_packet(port: &mut dyn TransferPort, timeout: Duration, crc: bool) -> Packet {
+    let start = Instant::now();
+    loop {
+        let size = match port.read(timeout.saturating_sub(start.elapsed())) {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) => return Packet::Eot,
+            Some(CAN) => {
+                if port.read(CHAR_TIMEOUT) == Some(CAN) {
+                    return Packet::Cancel;
+                }
+                continue;
+            }
+            Some(_) => continue,
+            None => return Packet::Timeout,
+        };
+        let check = if crc { 2 } else { 1 };
+        let mut buffer = Vec::with_capacity(size + 2 + check);
+        while buffer.len() < size + 2 + check {
+            match port.read(CHAR_TIMEOUT) {
+                Some(val) => buffer.push(val),
+                None => return Packet::Bad,
+            }
+        }
+        let num = buffer[0];
+        let data = buffer[2..size + 2].to_vec();
+        let valid = if crc {
+            crc16(&data).to_be_bytes() == buffer[size + 2..]
+        } else {
+            checksum(&data) == buffer[size + 2]
+        };
+        if num != !buffer[1] || !valid {
+            trace!("Bad block {}", num);
+            return Packet::Bad;
+        }
+        return Packet::Block(num, data);
+    }
+}
+
+
+// Receive the blocks of a file: the first CRC request has already been sent
+// Returns the data and the size of the last block that holds the padding
+fn receive_data(port: &mut dyn TransferPort, name: &str, total: Option<u64>, batch: bool)
+                -> Result<(Vec<u8>, usize), String> {
+    let mut data = Vec::new();
+    let mut last = 0;
+    // YMODEM always uses CRC
+    let mut crc = true;
+    let mut expected: u8 = 1;
+    let mut started = false;
+    let mut requests = 0;
+    let mut errors = 0;
+    let mut eots = 0;
+    loop {
+        if port.cancelled() {
+            cancel(port);
+            return Err(String::from(CANCELLED));
+        }
+        match read_packet(port, if started { BLOCK_TIMEOUT } else { START_INTERVAL }, crc) {
+            Packet::Block(num, block) => {
+                started = true;
+                if num == expected {
+                    last = block.len();
+                    data.extend_from_slice(&block);
+                    expected = expected.wrapping_add(1);
+                    errors = 0;
+                    port.write(&[ACK]);
+                    port.progress(name, data.len() as u64, total);
+                } else if num == expected.wrapping_sub(1) {
+                    // The sender did not get the acknowledge
+                    port.write(&[ACK]);
+                } else {
+                    cancel(port);
+                    return Err(format!("Received block {} but expected block {}", num, expected));
+                }
+            }
+            Packet::Eot => {
+                // YMODEM makes sure that the end of transmission is not just noise
+                if batch && eots == 0 {
+                    eots += 1;
+                    port.write(&[NAK]);
+                    continue;
+                }
+                port.write(&[ACK]);
+                return Ok((data, last));
+            }
+            Packet::Cancel => return Err(String::from("Cancelled by the sender")),
+            Packet::Timeout if !started => {
+                requests += 1;
+                if requests >= START_REQUESTS {
+                    cancel(port);
+                    return Err(String::from("The sender did not start"));
+                }
+                // Many senders (like boot ROMs) only know the checksum
+                if !batch && requests >= CRC_REQUESTS {
+                    crc = false;
+                }
+                port.write(&[if crc { CRC } else { NAK }]);
+            }
+            Packet::Timeout | Packet::Bad => {
+                errors += 1;
+                if errors >= RETRIES {
+                    cancel(port);
+                    return Err(String::from("Too many errors"));
+                }
+                port.purge();
+                port.write(&[NAK]);
+            }
+        }
+    }
+}
+
+
+// Remove the padding of the last block when the size of the file is not known
+// A file that ends with SUB characters loses them: XMODEM cannot tell them from the padding
+fn strip_padding(data: &mut Vec<u8>, last: usize) {
+    let block = data.len() - last;
+    while data.len() > block && data.last() == Some(&SUB) {
+        data.pop();
+    }
+}
+
+
+// Receive a file with XMODEM: the padding at the end of the last block is removed
+pub fn receive(port: &mut dyn TransferPort) -> Result<FileData, String> {
+    port.write(&[CRC]);
+    let (mut data, last) = receive_data(port, "XMODEM", None, false)?;
+    strip_padding(&mut data, last);
+    Ok(FileData { name: String::new(), data })
+}
+
+
+// Receive block 0 with the name and size of the next file in a YMODEM batch
+fn receive_header(port: &mut dyn TransferPort) -> Result<Vec<u8>, String> {
+    let mut requests = 0;
+    let mut errors = 0;
+    port.write(&[CRC]);
+    loop {
+        if port.cancelled() {
+            cancel(port);
+            return Err(String::from(CANCELLED));
+        }
+        match read_packet(port, START_INTERVAL, true) {
+            Packet::Block(0, block) => {
+                port.write(&[ACK]);
+                return Ok(block);
+            }
+            Packet::Block(..) | Packet::Eot => {
+                // Repeated end of the previous file
+                port.write(&[ACK]);
+            }
+            Packet::Cancel => return Err(String::from("Cancelled by the sender")),
+            Packet::Timeout => {
+                requests += 1;
+                if requests >= START_REQUESTS {
+                    cancel(port);
+                    return Err(String::from("The sender did not start"));
+                }
+                port.write(&[CRC]);
+            }
+            Packet::Bad => {
+                errors += 1;
+                if errors >= RETRIES {
+                    cancel(port);
+                    return Err(String::from("Too many errors"));
+                }
+                port.purge();
+                port.write(&[NAK]);
+            }
+        }
+    }
+}
+
+
+// Get the filename and the size from block 0: "name\0size mtime mode..."
+pub fn parse_header(block: &[u8]) -> (String, Option<u64>) {
+    let mut parts = block.splitn(2, |val| *val == 0);
+    let name = String::from_utf8_lossy(parts.next().unwrap_or_default()).to_string();
+    let size = parts.next()
+        .map(|rest| rest.iter().take_while(|val| val.is_ascii_digit()).map(|val| *val as char).collect::<String>())
+        .and_then(|size| size.parse::<u64>().ok());
+    (name, size)
+}
+
+
+// Receive files with YMODEM until the sender ends the batch
+pub fn receive_batch(port: &mut dyn TransferPort) -> Result<Vec<FileData>, String> {
+    let mut files = Vec::new();
+    loop {
+        let header = receive_header(port)?;
+        let (name, size) = parse_header(&header);
+        if name.is_empty() {
+            return Ok(files);
+        }
+        info!("YMODEM receive {} size {:?}", name, size);
+        port.write(&[CRC]);
+        let (mut data, last) = receive_data(port, &name, size, true)?;
+        match size {
+            Some(size) => data.truncate(usize::try_from(size).unwrap_or(usize::MAX)),
+            None => strip_padding(&mut data, last),
+        }
+        files.push(FileData { name, data });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::{loop_ports, test_data};
+    use std::thread;
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn xmodem_crc() {
+        let (mut sender, mut receiver) = loop_ports();
+        let file = FileData { name: String::from("test.bin"), data: test_data(1000) };
+        let expected = file.data.clone();
+        let thr = thread::spawn(move || send(&mut sender, &file, false));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received.data, expected);
+    }
+
+    #[test]
+    fn xmodem_1k_with_retransmission() {
+        let (mut sender, mut receiver) = loop_ports();
+        sender.corrupt = Some(2000);
+        let file = FileData { name: String::from("test.bin"), data: test_data(5000) };
+        let expected = file.data.clone();
+        let thr = thread::spawn(move || send(&mut sender, &file, true));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received.data, expected);
+    }
+
+    #[test]
+    fn xmodem_checksum_sender() {
+        let (mut sender, mut receiver) = loop_ports();
+        let file = FileData { name: String::from("test.bin"), data: test_data(300) };
+        let expected = file.data.clone();
+        // A sender that does not know CRC waits for a NAK
+        let thr = thread::spawn(move || {
+            while sender.read(START_TIMEOUT) != Some(NAK) {}
+            send_data(&mut sender, &file.name, &file.data, false, false)
+        });
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received.data, expected);
+    }
+
+    #[test]
+    fn padding() {
+        let mut data = [b"abc".to_vec(), vec![SUB; 130]].concat();
+        strip_padding(&mut data, 128);
+        assert_eq!(data, [b"abc".to_vec(), vec![SUB; 2]].concat());
+        let mut data = vec![SUB; 10];
+        strip_padding(&mut data, 0);
+        assert_eq!(data.len(), 10);
+    }
+
+    #[test]
+    fn ymodem_batch() {
+        let (mut sender, mut receiver) = loop_ports();
+        let files = vec![
+            FileData { name: String::from("first.bin"), data: test_data(3000) },
+            FileData { name: String::from("empty.txt"), data: Vec::new() },
+            FileData { name: String::from("padding.bin"), data: vec![SUB; 100] },
+        ];
+        let expected = files.clone();
+        let thr = thread::spawn(move || send_batch(&mut sender, &files));
+        let received = receive_batch(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn ymodem_header() {
+        assert_eq!(parse_header(b"image.bin\x001234 14567 100644\0\0"), (String::from("image.bin"), Some(1234)));
+        assert_eq!(parse_header(&[0; 128]), (String::new(), None));
+    }
+}
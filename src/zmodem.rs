@@ -0,0 +1,660 @@
+//Steen Hegelund
+//Time-Stamp: 2026-Oct-18 18:08
+//vim: set ts=4 sw=4 sts=4 tw=99 cc=120 et ft=rust :
+//
+// ZMODEM file transfer (streaming with 1K subpackets, CRC-16 and CRC-32)
+// The sender starts the receiver on the target by sending "rz\r" like sz does.
+
+use crate::file_transfer::{TransferPort, FileData};
+use crate::xmodem::{self, crc16, parse_header, CANCELLED};
+
+use log::{info, trace};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+const XON: u8 = 0x11;
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZCHALLENGE: u8 = 14;
+const ZCAN: u8 = 16;
+
+// Subpacket ends
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// Receiver capabilities
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+// Binary file conversion
+const ZCBIN: u8 = 1;
+
+const SUBPACKET: usize = 1024;
+const MAX_SUBPACKET: usize = 8192;
+// Subpackets sent before waiting for an acknowledge
+const FRAME_PACKETS: usize = 8;
+const RETRIES: usize = 10;
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const DATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[derive(Debug, PartialEq)]
+enum ReadError {
+    Timeout,
+    Garbled,
+    Cancelled,
+}
+
+
+#[derive(Debug)]
+struct Header {
+    kind: u8,
+    data: [u8; 4],
+    crc32: bool,
+}
+
+
+enum Escaped {
+    Byte(u8),
+    End(u8),
+}
+
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for val in data {
+        crc ^= u32::from(*val);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+// Positions are sent as little endian values in the header data
+fn pos_data(pos: u64) -> [u8; 4] {
+    (pos as u32).to_le_bytes()
+}
+
+
+fn data_pos(data: [u8; 4]) -> u64 {
+    u64::from(u32::from_le_bytes(data))
+}
+
+
+// Escape the bytes that may be eaten by the line or the target terminal
+fn escape(data: &[u8], out: &mut Vec<u8>) {
+    let mut last = 0;
+    for val in data {
+        match *val {
+            ZDLE | 0x10 | 0x11 | 0x13 | 0x90 | 0x91 | 0x93 => out.extend_from_slice(&[ZDLE, val ^ 0x40]),
+            // Avoid "@\r" which some targets use for telnet escapes
+            0x0d | 0x8d if last & 0x7f == b'@' => out.extend_from_slice(&[ZDLE, val ^ 0x40]),
+            _ => out.push(*val),
+        }
+        last = *val;
+    }
+}
+
+
+fn hex_header(kind: u8, data: [u8; 4]) -> Vec<u8> {
+    let mut frame = vec![kind];
+    frame.extend_from_slice(&data);
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    let mut out = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+    for val in frame {
+        out.extend_from_slice(format!("{:02x}", val).as_bytes());
+    }
+    out.extend_from_slice(&[b'\r', b'\n' | 0x80]);
+    if kind != ZACK && kind != ZFIN {
+        out.push(XON);
+    }
+    out
+}
+
+
+fn bin_header(kind: u8, data: [u8; 4], crc32: bool) -> Vec<u8> {
+    let mut frame = vec![kind];
+    frame.extend_from_slice(&data);
+    if crc32 {
+        let crc = self::crc32(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+    } else {
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut out = vec![ZPAD, ZDLE, if crc32 { ZBIN32 } else { ZBIN }];
+    escape(&frame, &mut out);
+    out
+}
+
+
+fn subpacket(data: &[u8], end: u8, crc32: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    escape(data, &mut out);
+    out.extend_from_slice(&[ZDLE, end]);
+    let mut frame = data.to_vec();
+    frame.push(end);
+    if crc32 {
+        escape(&self::crc32(&frame).to_le_bytes(), &mut out);
+    } else {
+        escape(&crc16(&frame).to_be_bytes(), &mut out);
+    }
+    if end == ZCRCW {
+        out.push(XON);
+    }
+    out
+}
+
+
+// Tell the other side that the transfer has been cancelled
+fn abort(port: &mut dyn TransferPort) -> String {
+    xmodem::cancel(port);
+    String::from(CANCELLED)
+}
+
+
+// Read a byte: flow control characters are dropped
+fn read_raw(port: &mut dyn TransferPort, timeout: Duration) -> Result<u8, ReadError> {
+    loop {
+        match port.read(timeout) {
+            Some(XON | 0x13 | 0x91 | 0x93) => (),
+            Some(val) => return Ok(val),
+            None => return Err(ReadError::Timeout),
+        }
+    }
+}
+
+
+fn read_escaped(port: &mut dyn TransferPort, timeout: Duration) -> Result<Escaped, ReadError> {
+    let val = read_raw(port, timeout)?;
+    if val != ZDLE {
+        return Ok(Escaped::Byte(val));
+    }
+    let mut cancels = 1;
+    loop {
+        match read_raw(port, timeout)? {
+            val @ (ZCRCE | ZCRCG | ZCRCQ | ZCRCW) => return Ok(Escaped::End(val)),
+            ZRUB0 => return Ok(Escaped::Byte(0x7f)),
+            ZRUB1 => return Ok(Escaped::Byte(0xff)),
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(ReadError::Cancelled);
+                }
+            }
+            val if val & 0x60 == 0x40 => return Ok(Escaped::Byte(val ^ 0x40)),
+            _ => return Err(ReadError::Garbled),
+        }
+    }
+}
+
+
+fn read_escaped_bytes(port: &mut dyn TransferPort, count: usize) -> Result<Vec<u8>, ReadError> {
+    let mut data = Vec::with_capacity(count);
+    while data.len() < count {
+        match read_escaped(port, DATA_TIMEOUT)? {
+            Escaped::Byte(val) => data.push(val),
+            Escaped::End(_) => return Err(ReadError::Garbled),
+        }
+    }
+    Ok(data)
+}
+
+
+fn read_hex_byte(port: &mut dyn TransferPort) -> Result<u8, ReadError> {
+    let text = [read_raw(port, DATA_TIMEOUT)?, read_raw(port, DATA_TIMEOUT)?];
+    std::str::from_utf8(&text).ok()
+        .and_then(|text| u8::from_str_radix(text, 16).ok())
+        .ok_or(ReadError::Garbled)
+}
+
+
+// Find and read the next header: other bytes are skipped
+fn read_header(port: &mut dyn TransferPort, timeout: Duration) -> Result<Header, ReadError> {
+    let start = Instant::now();
+    let mut pad = false;
+    let mut cancels = 0;
+    let format = loop {
+        match read_raw(port, timeout.saturating_sub(start.elapsed()))? {
+            ZPAD => {
+                pad = true;
+                cancels = 0;
+            }
+            ZDLE if pad => {
+                let format = read_raw(port, DATA_TIMEOUT)?;
+                if matches!(format, ZBIN | ZHEX | ZBIN32) {
+                    break format;
+                }
+                pad = false;
+            }
+            ZDLE => {
+                cancels += 1;
+                if cancels >= 5 {
+                    return Err(ReadError::Cancelled);
+                }
+            }
+            _ => {
+                pad = false;
+                cancels = 0;
+            }
+        }
+    };
+    let (frame, valid) = match format {
+        ZHEX => {
+            let mut frame = Vec::with_capacity(7);
+            for _ in 0..7 {
+                frame.push(read_hex_byte(port)?);
+            }
+            let valid = crc16(&frame[..5]).to_be_bytes() == frame[5..];
+            (frame, valid)
+        }
+        ZBIN => {
+            let frame = read_escaped_bytes(port, 7)?;
+            let valid = crc16(&frame[..5]).to_be_bytes() == frame[5..];
+            (frame, valid)
+        }
+        _ => {
+            let frame = read_escaped_bytes(port, 9)?;
+            let valid = crc32(&frame[..5]).to_le_bytes() == frame[5..];
+            (frame, valid)
+        }
+    };
+    if !valid {
+        trace!("Header with bad CRC: {:02x?}", frame);
+        return Err(ReadError::Garbled);
+    }
+    let header = Header {
+        kind: frame[0],
+        data: [frame[1], frame[2], frame[3], frame[4]],
+        crc32: format == ZBIN32,
+    };
+    trace!("Header: {:?}", header);
+    Ok(header)
+}
+
+
+// Read a data subpacket and its end type: the CRC type follows the header that started the frame
+fn read_subpacket(port: &mut dyn TransferPort, crc32: bool) -> Result<(Vec<u8>, u8), ReadError> {
+    let mut data = Vec::with_capacity(SUBPACKET);
+    loop {
+        match read_escaped(port, DATA_TIMEOUT)? {
+            Escaped::Byte(val) => {
+                data.push(val);
+                if data.len() > MAX_SUBPACKET {
+                    return Err(ReadError::Garbled);
+                }
+            }
+            Escaped::End(end) => {
+                let mut frame = data.clone();
+                frame.push(end);
+                let valid = if crc32 {
+                    read_escaped_bytes(port, 4)? == self::crc32(&frame).to_le_bytes()
+                } else {
+                    read_escaped_bytes(port, 2)? == crc16(&frame).to_be_bytes()
+                };
+                if !valid {
+                    trace!("Subpacket with bad CRC at size {}", data.len());
+                    return Err(ReadError::Garbled);
+                }
+                return Ok((data, end));
+            }
+        }
+    }
+}
+
+
+// Start the receiver and get its capabilities: true if it handles CRC-32
+fn wait_receiver(port: &mut dyn TransferPort) -> Result<bool, String> {
+    let start = Instant::now();
+    port.write(b"rz\r");
+    port.write(&hex_header(ZRQINIT, [0; 4]));
+    while start.elapsed() < START_TIMEOUT {
+        if port.cancelled() {
+            return Err(abort(port));
+        }
+        match read_header(port, HEADER_TIMEOUT) {
+            Ok(hdr) if hdr.kind == ZRINIT => return Ok(hdr.data[3] & CANFC32 != 0),
+            Ok(hdr) if hdr.kind == ZCHALLENGE => port.write(&hex_header(ZACK, hdr.data)),
+            Ok(_) | Err(ReadError::Garbled) => (),
+            Err(ReadError::Timeout) => port.write(&hex_header(ZRQINIT, [0; 4])),
+            Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the receiver")),
+        }
+    }
+    Err(String::from("The receiver did not start"))
+}
+
+
+// Offer a file to the receiver: returns the position to start from or None if it is skipped
+fn send_file_info(port: &mut dyn TransferPort, file: &FileData, crc32: bool) -> Result<Option<u64>, String> {
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let info = format!("{}\0{} {:o} 100644 0 1 {}\0", file.name, file.data.len(), mtime, file.data.len());
+    for _ in 0..RETRIES {
+        let mut frame = bin_header(ZFILE, [0, 0, 0, ZCBIN], crc32);
+        frame.extend_from_slice(&subpacket(info.as_bytes(), ZCRCW, crc32));
+        port.write(&frame);
+        loop {
+            if port.cancelled() {
+                return Err(abort(port));
+            }
+            match read_header(port, HEADER_TIMEOUT) {
+                Ok(hdr) if hdr.kind == ZRPOS => return Ok(Some(data_pos(hdr.data))),
+                Ok(hdr) if hdr.kind == ZSKIP => return Ok(None),
+                // A repeated ZRINIT: the answer to the file may follow
+                Ok(hdr) if hdr.kind == ZRINIT => (),
+                Ok(_) | Err(ReadError::Timeout) | Err(ReadError::Garbled) => break,
+                Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the receiver")),
+            }
+        }
+    }
+    abort(port);
+    Err(String::from("The receiver did not accept the file"))
+}
+
+
+// Stream the file from a position in frames that end with a subpacket that must be acknowledged
+fn send_file_data(port: &mut dyn TransferPort, file: &FileData, mut pos: u64, crc32: bool) -> Result<(), String> {
+    let total = file.data.len() as u64;
+    let mut errors = 0;
+    loop {
+        if port.cancelled() {
+            return Err(abort(port));
+        }
+        pos = pos.min(total);
+        port.write(&bin_header(ZDATA, pos_data(pos), crc32));
+        for count in 1..=FRAME_PACKETS {
+            let start = pos as usize;
+            let end = (start + SUBPACKET).min(file.data.len());
+            let kind = if end == file.data.len() {
+                ZCRCE
+            } else if count == FRAME_PACKETS {
+                ZCRCW
+            } else {
+                ZCRCG
+            };
+            port.write(&subpacket(&file.data[start..end], kind, crc32));
+            pos = end as u64;
+            port.progress(&file.name, pos, Some(total));
+            if kind != ZCRCG {
+                break;
+            }
+        }
+        if pos == total {
+            port.write(&bin_header(ZEOF, pos_data(pos), crc32));
+        }
+        // Wait for the acknowledge of the frame or the end of the file
+        loop {
+            if port.cancelled() {
+                return Err(abort(port));
+            }
+            match read_header(port, HEADER_TIMEOUT) {
+                Ok(hdr) if hdr.kind == ZACK && pos < total && data_pos(hdr.data) == pos => {
+                    errors = 0;
+                    break;
+                }
+                Ok(hdr) if hdr.kind == ZRINIT && pos == total => return Ok(()),
+                Ok(hdr) if hdr.kind == ZRPOS => {
+                    trace!("Receiver wants position {} instead of {}", data_pos(hdr.data), pos);
+                    pos = data_pos(hdr.data);
+                    errors += 1;
+                    break;
+                }
+                Ok(hdr) if hdr.kind == ZSKIP => return Ok(()),
+                Ok(hdr) if hdr.kind == ZNAK => {
+                    errors += 1;
+                    break;
+                }
+                Ok(_) => (),
+                Err(ReadError::Timeout) | Err(ReadError::Garbled) => {
+                    errors += 1;
+                    break;
+                }
+                Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the receiver")),
+            }
+        }
+        if errors >= RETRIES {
+            abort(port);
+            return Err(String::from("Too many errors"));
+        }
+    }
+}
+
+
+// End the session: the receiver answers ZFIN and the sender ends with "OO"
+fn finish(port: &mut dyn TransferPort) -> Result<(), String> {
+    for _ in 0..RETRIES {
+        port.write(&hex_header(ZFIN, [0; 4]));
+        match read_header(port, HEADER_TIMEOUT) {
+            Ok(hdr) if hdr.kind == ZFIN => {
+                port.write(b"OO");
+                return Ok(());
+            }
+            Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the receiver")),
+            _ => {
+                if port.cancelled() {
+                    return Err(abort(port));
+                }
+            }
+        }
+    }
+    Err(String::from("The receiver did not end the session"))
+}
+
+
+// Send files with ZMODEM
+pub fn send(port: &mut dyn TransferPort, files: &[FileData]) -> Result<(), String> {
+    let crc32 = wait_receiver(port)?;
+    info!("ZMODEM receiver ready, CRC-32: {}", crc32);
+    for file in files {
+        match send_file_info(port, file, crc32)? {
+            Some(pos) => send_file_data(port, file, pos, crc32)?,
+            None => info!("ZMODEM receiver skipped {}", file.name),
+        }
+    }
+    finish(port)
+}
+
+
+// Receive files with ZMODEM until the sender ends the session
+pub fn receive(port: &mut dyn TransferPort) -> Result<Vec<FileData>, String> {
+    let zrinit = hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32]);
+    let mut files = Vec::new();
+    let mut current: Option<(FileData, Option<u64>)> = None;
+    let mut timeouts = 0;
+    let mut errors = 0;
+    port.write(&zrinit);
+    loop {
+        if port.cancelled() {
+            return Err(abort(port));
+        }
+        if errors >= RETRIES {
+            abort(port);
+            return Err(String::from("Too many errors"));
+        }
+        let hdr = match read_header(port, HEADER_TIMEOUT) {
+            Ok(hdr) => {
+                timeouts = 0;
+                hdr
+            }
+            Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the sender")),
+            Err(err) => {
+                if err == ReadError::Timeout {
+                    timeouts += 1;
+                    if timeouts as u32 * HEADER_TIMEOUT >= START_TIMEOUT {
+                        abort(port);
+                        return Err(String::from("The sender did not respond"));
+                    }
+                } else {
+                    errors += 1;
+                }
+                match &current {
+                    Some((file, _)) => port.write(&hex_header(ZRPOS, pos_data(file.data.len() as u64))),
+                    None => port.write(&zrinit),
+                }
+                continue;
+            }
+        };
+        match hdr.kind {
+            ZRQINIT => port.write(&zrinit),
+            ZSINIT => match read_subpacket(port, hdr.crc32) {
+                Ok(_) => port.write(&hex_header(ZACK, [0; 4])),
+                Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the sender")),
+                Err(_) => {
+                    errors += 1;
+                    port.write(&hex_header(ZNAK, [0; 4]));
+                }
+            },
+            ZFILE => match read_subpacket(port, hdr.crc32) {
+                Ok((info, _)) => {
+                    let (name, size) = parse_header(&info);
+                    info!("ZMODEM receive {} size {:?}", name, size);
+                    current = Some((FileData { name, data: Vec::new() }, size));
+                    port.write(&hex_header(ZRPOS, pos_data(0)));
+                }
+                Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the sender")),
+                Err(_) => {
+                    errors += 1;
+                    port.write(&hex_header(ZNAK, [0; 4]));
+                }
+            },
+            ZDATA => {
+                let Some((file, size)) = current.as_mut() else {
+                    port.write(&zrinit);
+                    continue;
+                };
+                if data_pos(hdr.data) != file.data.len() as u64 {
+                    port.write(&hex_header(ZRPOS, pos_data(file.data.len() as u64)));
+                    continue;
+                }
+                loop {
+                    match read_subpacket(port, hdr.crc32) {
+                        Ok((data, end)) => {
+                            errors = 0;
+                            file.data.extend_from_slice(&data);
+                            port.progress(&file.name, file.data.len() as u64, *size);
+                            let pos = pos_data(file.data.len() as u64);
+                            match end {
+                                ZCRCQ => port.write(&hex_header(ZACK, pos)),
+                                ZCRCW => {
+                                    port.write(&hex_header(ZACK, pos));
+                                    break;
+                                }
+                                ZCRCE => break,
+                                _ => (),
+                            }
+                        }
+                        Err(ReadError::Cancelled) => return Err(String::from("Cancelled by the sender")),
+                        Err(_) => {
+                            // The rest of the frame is skipped while looking for the next header
+                            errors += 1;
+                            port.write(&hex_header(ZRPOS, pos_data(file.data.len() as u64)));
+                            break;
+                        }
+                    }
+                }
+            }
+            ZEOF => {
+                if current.as_ref().is_some_and(|(file, _)| file.data.len() as u64 == data_pos(hdr.data)) {
+                    if let Some((file, _)) = current.take() {
+                        files.push(file);
+                    }
+                    port.write(&zrinit);
+                } else if current.is_none() {
+                    // The sender did not get the answer to the end of the file
+                    port.write(&zrinit);
+                }
+            }
+            ZFIN => {
+                port.write(&hex_header(ZFIN, [0; 4]));
+                // Consume the "OO" that ends the session
+                for _ in 0..2 {
+                    if port.read(Duration::from_millis(500)) != Some(b'O') {
+                        break;
+                    }
+                }
+                return Ok(files);
+            }
+            ZCAN | ZABORT => return Err(String::from("Cancelled by the sender")),
+            _ => (),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::tests::{loop_ports, test_data};
+    use std::thread;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn headers_and_subpackets() {
+        let (mut sender, mut receiver) = loop_ports();
+        let data = test_data(600);
+        for crc32 in [false, true] {
+            sender.write(&hex_header(ZRPOS, pos_data(0x1811)));
+            sender.write(&bin_header(ZDATA, pos_data(0x9013), crc32));
+            sender.write(&subpacket(&data, ZCRCW, crc32));
+            let hdr = read_header(&mut receiver, HEADER_TIMEOUT).unwrap();
+            assert_eq!((hdr.kind, data_pos(hdr.data), hdr.crc32), (ZRPOS, 0x1811, false));
+            let hdr = read_header(&mut receiver, HEADER_TIMEOUT).unwrap();
+            assert_eq!((hdr.kind, data_pos(hdr.data), hdr.crc32), (ZDATA, 0x9013, crc32));
+            assert_eq!(read_subpacket(&mut receiver, crc32).unwrap(), (data.clone(), ZCRCW));
+        }
+    }
+
+    #[test]
+    fn send_and_receive() {
+        let (mut sender, mut receiver) = loop_ports();
+        let files = vec![
+            FileData { name: String::from("large.bin"), data: test_data(20000) },
+            FileData { name: String::from("empty.txt"), data: Vec::new() },
+            FileData { name: String::from("escapes.bin"), data: b"\x18\x10\x11\x13\x90\x91\x93@\r**\x18B".to_vec() },
+        ];
+        let expected = files.clone();
+        let thr = thread::spawn(move || send(&mut sender, &files));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn resend_after_corruption() {
+        let (mut sender, mut receiver) = loop_ports();
+        sender.corrupt = Some(5000);
+        let files = vec![FileData { name: String::from("test.bin"), data: test_data(30000) }];
+        let expected = files.clone();
+        let thr = thread::spawn(move || send(&mut sender, &files));
+        let received = receive(&mut receiver).unwrap();
+        assert_eq!(thr.join().unwrap(), Ok(()));
+        assert_eq!(received, expected);
+    }
+}